@@ -17,6 +17,39 @@ pub enum Commands {
 
         /// Print accumulator value after each instruction.
         #[arg(short, long)]
-        trace: bool
+        trace: bool,
+
+        /// Define a constant for the program to use, can be given many times.
+        #[arg(short='D', value_name="NAME=VALUE", value_parser=aaaasm::parser::parse_define)]
+        define: Vec<(String, i32)>,
+
+        /// Start at this label or instruction, instead of where the program says.
+        #[arg(long, value_name="TARGET")]
+        entry: Option<String>,
+
+        /// The number of registers, instead of what the program says.
+        #[arg(long, value_name="COUNT")]
+        registers: Option<usize>,
+
+        /// The size of memory in words, instead of what the program says.
+        #[arg(long, value_name="WORDS")]
+        memory: Option<usize>,
+
+        /// What to do when arithmetic overflows, instead of what the program says:
+        /// wrapping, saturating or trapping.
+        #[arg(long, value_name="MODE")]
+        overflow: Option<aaaasm::interpreter::OverflowMode>,
+    },
+    /// Rewrite .aaaasm files into the canonical layout
+    Fmt {
+        /// The .aaaasm files to format
+        #[arg(required=true)]
+        files: Vec<String>,
+
+        /// Don't write anything, fail if any file isn't formatted.
+        #[arg(long)]
+        check: bool,
     },
+    /// Print a reference of every instruction, as markdown
+    Isa,
 }
@@ -0,0 +1,69 @@
+use std::fmt;
+
+use crate::interpreter::Instruction;
+
+/// A fault that stopped the program. Each one has the index of the
+/// instruction that was running and the instruction itself, along with the
+/// value that caused the fault.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum RuntimeError {
+    BadRegister { pc: usize, instruction: Instruction, register: i32, count: usize },
+    MemoryOutOfBounds { pc: usize, instruction: Instruction, address: i32, size: usize },
+    BadJumpTarget { pc: usize, instruction: Instruction, target: i32, len: usize },
+    // there is no instruction at the PC to run
+    PcOutOfRange { pc: usize, len: usize },
+    // `result` is what the answer would have been with enough bits
+    Overflow { pc: usize, instruction: Instruction, result: i64 },
+    // `dividend` is the accumulator
+    DivisionByZero { pc: usize, instruction: Instruction, dividend: i32 },
+}
+
+impl RuntimeError {
+    pub fn pc(&self) -> usize {
+        return match self {
+            RuntimeError::BadRegister { pc, .. }
+                | RuntimeError::MemoryOutOfBounds { pc, .. }
+                | RuntimeError::BadJumpTarget { pc, .. }
+                | RuntimeError::PcOutOfRange { pc, .. }
+                | RuntimeError::Overflow { pc, .. }
+                | RuntimeError::DivisionByZero { pc, .. } => *pc,
+        };
+    }
+
+    /// The instruction that faulted, None if there wasn't one to run.
+    pub fn instruction(&self) -> Option<Instruction> {
+        return match self {
+            RuntimeError::BadRegister { instruction, .. }
+                | RuntimeError::MemoryOutOfBounds { instruction, .. }
+                | RuntimeError::BadJumpTarget { instruction, .. }
+                | RuntimeError::Overflow { instruction, .. }
+                | RuntimeError::DivisionByZero { instruction, .. } => Some(*instruction),
+            RuntimeError::PcOutOfRange { .. } => None,
+        };
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            RuntimeError::BadRegister { register, count, .. } =>
+                write!(f, "Illegal register access! Attempted to access r{} but there are only {} registers",
+                       register, count),
+            RuntimeError::MemoryOutOfBounds { address, size, .. } =>
+                write!(f, "Attempted to access memory out of bounds! Accessed {} but the mem size is {}",
+                       address, size),
+            RuntimeError::BadJumpTarget { target, len, .. } =>
+                write!(f, "Illegal jump action. Tried to jump to {} but there are only {} instructions",
+                       target, len),
+            RuntimeError::PcOutOfRange { pc, len } =>
+                write!(f, "Attempted to execute instruction at idx {}, but there are only {} instructions",
+                       pc, len),
+            RuntimeError::Overflow { result, .. } =>
+                write!(f, "Overflow! The result {} does not fit in 32 bits", result),
+            RuntimeError::DivisionByZero { dividend, .. } =>
+                write!(f, "Division by zero! Attempted to divide {} by 0", dividend),
+        };
+    }
+}
+
+impl std::error::Error for RuntimeError {}
@@ -0,0 +1,192 @@
+// The instruction set. Everything about an instruction is written once in
+// the table at the bottom of this file: its mnemonic, its operands, what it
+// does and the function that runs it. The `Instruction` enum, its Display,
+// the parser's view of each instruction, the dispatcher and the reference
+// printed by `aaaasm isa` all come from that table.
+
+use crate::interpreter::*;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OperandKind {
+    Register, // written as rN
+    Number, // an immediate value or memory address
+    Target, // the index of an instruction to jump to
+}
+
+impl OperandKind {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            OperandKind::Register => "register",
+            // targets are written like any other number
+            OperandKind::Number | OperandKind::Target => "number",
+        };
+    }
+}
+
+/// What there is to know about an instruction, taken from the table.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct InstructionInfo {
+    pub mnemonic: &'static str,
+    pub operands: &'static [OperandKind],
+    // what each operand stands for, for documentation
+    pub operand_names: &'static [&'static str],
+    pub description: &'static str,
+}
+
+macro_rules! instructions {
+    ($( $name:ident ( $($operand:ident : $kind:ident),* ) => $handler:ident, $description:literal; )*) => {
+        #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+        pub enum Instruction {
+            $(
+                #[doc = $description]
+                $name($(instructions!(@i32 $operand)),*),
+            )*
+        }
+
+        /// Every instruction, in the order they're documented.
+        pub const INSTRUCTIONS: &[InstructionInfo] = &[
+            $(
+                InstructionInfo {
+                    mnemonic: stringify!($name),
+                    operands: &[$(OperandKind::$kind),*],
+                    operand_names: &[$(stringify!($operand)),*],
+                    description: $description,
+                },
+            )*
+        ];
+
+        impl Instruction {
+            pub fn info(&self) -> &'static InstructionInfo {
+                let mut idx = 0;
+                $(
+                    if let Instruction::$name(..) = self {
+                        return &INSTRUCTIONS[idx];
+                    }
+                    idx += 1;
+                )*
+                unreachable!("instruction {} is missing from the table", idx);
+            }
+
+            pub fn operands(&self) -> Vec<i32> {
+                return match self {
+                    $( Instruction::$name($($operand),*) => vec![$(*$operand),*], )*
+                };
+            }
+
+            /// Builds an instruction from its mnemonic, which must be upper
+            /// case, and its operands. None if the mnemonic doesn't exist or
+            /// the number of operands is wrong.
+            pub fn from_parts(mnemonic: &str, operands: &[i32]) -> Option<Instruction> {
+                let mut operands = operands.iter().copied();
+                let ins = match mnemonic {
+                    $( stringify!($name) => Instruction::$name($(instructions!(@next operands $operand)),*), )*
+                    _ => return None,
+                };
+                return match operands.next() {
+                    Some(_) => None,
+                    None => Some(ins),
+                };
+            }
+
+            pub(super) fn execute(&self, s: &mut Interpreter) -> InstructionReturn {
+                return match self {
+                    $( Instruction::$name($($operand),*) => $handler(s, $(*$operand),*), )*
+                };
+            }
+        }
+    };
+    (@i32 $operand:ident) => { i32 };
+    (@next $operands:ident $operand:ident) => { $operands.next()? };
+}
+
+impl std::fmt::Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let info = self.info();
+        write!(f, "{}", info.mnemonic)?;
+        for (value, kind) in self.operands().iter().zip(info.operands.iter()) {
+            match kind {
+                OperandKind::Register => write!(f, " r{}", value)?,
+                OperandKind::Number | OperandKind::Target => write!(f, " {}", value)?,
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// A markdown reference of every instruction, generated from the table.
+pub fn isa_reference() -> String {
+    let mut out = String::from("| Instruction | Operands | Description |\n| --- | --- | --- |\n");
+    for info in INSTRUCTIONS.iter() {
+        let operands: Vec<_> = info.operand_names.iter().zip(info.operands.iter())
+            .map(|(name, kind)| format!("{} ({})", name, kind.name()))
+            .collect();
+        out += &format!("| `{}` | {} | {} |\n", info.mnemonic, operands.join(", "), info.description);
+    }
+    return out;
+}
+
+instructions! {
+    // special instructions
+    NOOP() => NOOP, "Does nothing.";
+
+    // load instructions
+    LOAD(value: Number) => LOAD, "Loads an immediate value into the accumulator.";
+    R2A_LOAD(reg: Register) => R2A_LOAD, "Loads a register into the accumulator.";
+    M2R_LOAD(address: Number, reg: Register) => M2R_LOAD, "Loads a word of memory into a register.";
+    M2A_LOAD(address: Number) => M2A_LOAD, "Loads a word of memory into the accumulator.";
+
+    // store instructions
+    A2R_STORE(reg: Register) => A2R_STORE, "Stores the accumulator in a register.";
+    A2M_STORE(address: Number) => A2M_STORE, "Stores the accumulator in memory.";
+    R2M_STORE(reg: Register, address: Number) => R2M_STORE, "Stores a register in memory.";
+
+    // maths instructions
+    I_ADD(value: Number) => I_ADD, "Adds an immediate value to the accumulator.";
+    R_ADD(reg: Register) => R_ADD, "Adds a register to the accumulator.";
+    I_SUB(value: Number) => I_SUB, "Subtracts an immediate value from the accumulator.";
+    R_SUB(reg: Register) => R_SUB, "Subtracts a register from the accumulator.";
+    I_MUL(value: Number) => I_MUL, "Multiplies the accumulator by an immediate value.";
+    R_MUL(reg: Register) => R_MUL, "Multiplies the accumulator by a register.";
+    I_DIV(value: Number) => I_DIV, "Divides the accumulator by an immediate value, rounding towards zero.";
+    R_DIV(reg: Register) => R_DIV, "Divides the accumulator by a register, rounding towards zero.";
+    I_MOD(value: Number) => I_MOD, "Sets the accumulator to the remainder of dividing it by an immediate value, which has the sign of the accumulator.";
+    R_MOD(reg: Register) => R_MOD, "Sets the accumulator to the remainder of dividing it by a register, which has the sign of the accumulator.";
+    NEG() => NEG, "Negates the accumulator.";
+
+    // bitwise instructions, shifts by a negative amount or by 32 or more
+    // shift every bit out
+    I_AND(value: Number) => I_AND, "Bitwise ands the accumulator with an immediate value.";
+    R_AND(reg: Register) => R_AND, "Bitwise ands the accumulator with a register.";
+    I_OR(value: Number) => I_OR, "Bitwise ors the accumulator with an immediate value.";
+    R_OR(reg: Register) => R_OR, "Bitwise ors the accumulator with a register.";
+    I_XOR(value: Number) => I_XOR, "Bitwise xors the accumulator with an immediate value.";
+    R_XOR(reg: Register) => R_XOR, "Bitwise xors the accumulator with a register.";
+    NOT() => NOT, "Flips every bit of the accumulator.";
+    I_SHL(amount: Number) => I_SHL, "Shifts the accumulator left by an immediate amount, filling with zeros.";
+    R_SHL(reg: Register) => R_SHL, "Shifts the accumulator left by the amount in a register, filling with zeros.";
+    I_SHR(amount: Number) => I_SHR, "Shifts the accumulator right by an immediate amount, filling with zeros.";
+    R_SHR(reg: Register) => R_SHR, "Shifts the accumulator right by the amount in a register, filling with zeros.";
+    I_SAR(amount: Number) => I_SAR, "Shifts the accumulator right by an immediate amount, keeping its sign.";
+    R_SAR(reg: Register) => R_SAR, "Shifts the accumulator right by the amount in a register, keeping its sign.";
+
+    // comparisons, which only set the flags
+    CMP(value: Number) => CMP, "Sets the flags as if subtracting an immediate value from the accumulator.";
+    R_CMP(reg: Register) => R_CMP, "Sets the flags as if subtracting a register from the accumulator.";
+
+    // jump instructions, "greater" and "less" compare as signed numbers
+    // after a CMP
+    JUMP(target: Target) => JUMP, "Jumps to an instruction.";
+    JUMP_NEG(target: Target) => JUMP_NEG, "Jumps to an instruction if the accumulator is negative.";
+    JUMP_ZERO(target: Target) => JUMP_ZERO, "Jumps to an instruction if the zero flag is set.";
+    JUMP_NZ(target: Target) => JUMP_NZ, "Jumps to an instruction if the zero flag is clear.";
+    JUMP_POS(target: Target) => JUMP_POS, "Jumps to an instruction if the zero and negative flags are clear.";
+    JUMP_MI(target: Target) => JUMP_MI, "Jumps to an instruction if the negative flag is set.";
+    JUMP_GT(target: Target) => JUMP_GT, "Jumps to an instruction if the last comparison was greater.";
+    JUMP_GE(target: Target) => JUMP_GE, "Jumps to an instruction if the last comparison was greater or equal.";
+    JUMP_LT(target: Target) => JUMP_LT, "Jumps to an instruction if the last comparison was less.";
+    JUMP_LE(target: Target) => JUMP_LE, "Jumps to an instruction if the last comparison was less or equal.";
+    JUMP_CARRY(target: Target) => JUMP_CARRY, "Jumps to an instruction if the carry flag is set.";
+    JUMP_NC(target: Target) => JUMP_NC, "Jumps to an instruction if the carry flag is clear.";
+    JUMP_OVF(target: Target) => JUMP_OVF, "Jumps to an instruction if the overflow flag is set.";
+    JUMP_NOVF(target: Target) => JUMP_NOVF, "Jumps to an instruction if the overflow flag is clear.";
+}
@@ -1,242 +1,544 @@
 #![allow(non_snake_case)]
 #![allow(non_camel_case_types)]
+mod error;
+mod isa;
 mod tests;
 
-const REG_NUMBER:usize = 4;
-const MEM_SIZE:usize = 1024;
+pub use error::RuntimeError;
+pub use isa::{isa_reference, Instruction, InstructionInfo, OperandKind, INSTRUCTIONS};
+
+// the size of the machine unless a program asks for another
+pub const REG_NUMBER:usize = 4;
+pub const MEM_SIZE:usize = 1024;
+
+// the most a program can ask for
+pub const MAX_REGISTERS:usize = 1 << 16;
+pub const MAX_MEMORY:usize = 1 << 24;
 
 // bool is whether or not to increase the PC
-type InstructionReturn = Result<bool, String>;
+type InstructionReturn = Result<bool, RuntimeError>;
 
+/// Words to write into memory before the program starts.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DataBlock {
+    pub address: usize,
+    pub words: Vec<i32>,
+}
+
+/// Where an instruction was written, so messages can point back at it.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: usize,
+    // the line as written, without surrounding whitespace
+    pub text: String,
+}
+
+impl std::fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "`{}` at {}:{}", self.text, self.file, self.line)
+    }
+}
+
+/// What happens when the result of arithmetic doesn't fit in 32 bits.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum OverflowMode {
+    // keep the low 32 bits
+    Wrapping,
+    // stop at the largest or smallest value
+    Saturating,
+    // stop the program with `RuntimeError::Overflow`
+    #[default]
+    Trapping,
+}
+
+impl std::str::FromStr for OverflowMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OverflowMode, String> {
+        return match s {
+            "wrapping" => Ok(OverflowMode::Wrapping),
+            "saturating" => Ok(OverflowMode::Saturating),
+            "trapping" => Ok(OverflowMode::Trapping),
+            _ => Err(format!("unknown overflow mode `{}`, expected wrapping, saturating or trapping", s)),
+        };
+    }
+}
+
+/// The shape of the machine a program runs on.
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
-pub enum Instruction {
-    NOOP(),
-    LOAD(i32), // LOAD IMMEDIATE INTO ACC
-    R2A_LOAD(i32), // LOAD FROM REG INTO ACC
-    M2R_LOAD(i32, i32), // LOAD FROM MEMORY TO REG
-    M2A_LOAD(i32), // LOAD FROM MEMORY TO ACC
-    A2R_STORE(i32), // STORE FROM ACC INTO REG
-    A2M_STORE(i32), // STORE FROM ACC INTO MEM
-    R2M_STORE(i32, i32), // STORE FROM REG INTO MEM
-    I_ADD(i32), // ADD IMMEDIATE TO ACC
-    R_ADD(i32), // ADD REGISTER TO ACC
-    JUMP(i32), // ALWAYS JUMP TO IMMEDIATE
-    JUMP_NEG(i32), // JUMP TO IMMEDIATE IF ACC < 0
-}
-
-impl std::fmt::Display for Instruction {
+pub struct MachineConfig {
+    pub registers: usize,
+    // in words
+    pub memory: usize,
+    // the index of the first instruction to run
+    pub initial_pc: usize,
+    pub overflow: OverflowMode,
+}
+
+impl Default for MachineConfig {
+    fn default() -> MachineConfig {
+        return MachineConfig {
+            registers: REG_NUMBER,
+            memory: MEM_SIZE,
+            initial_pc: 0,
+            overflow: OverflowMode::default(),
+        };
+    }
+}
+
+/// The status flags. Every instruction that changes the accumulator sets
+/// zero and negative from the new value. Arithmetic also sets carry if the
+/// same operation on the values as unsigned numbers didn't fit, and
+/// overflow if the exact result didn't fit in 32 bits. Anything else that
+/// changes the accumulator clears carry and overflow. `CMP` sets the flags
+/// like a wrapping subtraction without changing the accumulator, so the
+/// signed jumps are always right after a `CMP`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Flags {
+    pub zero: bool,
+    pub negative: bool,
+    pub carry: bool,
+    pub overflow: bool,
+}
+
+impl Flags {
+    // the flags for a result that fitted in 32 bits
+    fn of(value: i32) -> Flags {
+        return Flags { zero: value == 0, negative: value < 0, carry: false, overflow: false };
+    }
+}
+
+// written like `Z-C-`, with a dash for each flag that isn't set
+impl std::fmt::Display for Flags {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Instruction::NOOP() => write!(f, "NOOP"),
-            Instruction::LOAD(x) => write!(f, "LOAD {}", x),
-            Instruction::R2A_LOAD(r) => write!(f, "R2A_LOAD r{}", r),
-            Instruction::M2R_LOAD(m, r) => write!(f, "M2R_LOAD {} r{}", m , r),
-            Instruction::M2A_LOAD(m) => write!(f, "M2A_LOAD {}", m),
-            Instruction::A2R_STORE(r) => write!(f, "A2R_STORE r{}", r),
-            Instruction::A2M_STORE(m) => write!(f, "A2M_STORE {}", m),
-            Instruction::R2M_STORE(r, m) => write!(f, "R2M_STORE r{} {}", r, m),
-            Instruction::I_ADD(x) => write!(f, "I_ADD {}", x),
-            Instruction::R_ADD(r) => write!(f, "R_ADD r{}", r),
-            // NI because of impending rework to JUMP instructions
-            Instruction::JUMP(_) => todo!(),
-            Instruction::JUMP_NEG(_) => todo!(),
+        let flags = [(self.zero, 'Z'), (self.negative, 'N'), (self.carry, 'C'), (self.overflow, 'V')];
+        for (set, name) in flags {
+            write!(f, "{}", if set { name } else { '-' })?;
         }
+        return Ok(());
     }
 }
 
+/// Everything the parser produces for a program.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Program {
+    pub instructions: Vec<Instruction>,
+    pub data: Vec<DataBlock>,
+    // one entry per instruction
+    pub source_map: Vec<SourceLocation>,
+    // the machine to run on, which starts at the program's entry point
+    pub machine: MachineConfig,
+}
 
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Interpreter {
     instructions: Vec<Instruction>,
     pc: usize,
     pub accumulator: i32,
-    registers: [i32; REG_NUMBER],
-    memory: [i32; MEM_SIZE],
+    registers: Vec<i32>,
+    memory: Vec<i32>,
+    overflow_mode: OverflowMode,
+    pub flags: Flags,
+    // empty if the instructions didn't come from source
+    source_map: Vec<SourceLocation>,
     pub trace: bool
 }
 
 impl Interpreter {
     pub fn new(ins: Vec<Instruction>) -> Interpreter {
+        return Interpreter::with_config(ins, MachineConfig::default());
+    }
+
+    pub fn with_config(ins: Vec<Instruction>, config: MachineConfig) -> Interpreter {
         Interpreter {
             instructions: ins,
-            pc: 0,
+            pc: config.initial_pc,
             accumulator: 0,
-            registers: [0; REG_NUMBER],
-            memory: [0; MEM_SIZE],
+            registers: vec![0; config.registers],
+            memory: vec![0; config.memory],
+            overflow_mode: config.overflow,
+            flags: Flags::default(),
+            source_map: Vec::new(),
             trace: false,
         }
     }
 
-    pub fn run_single(&mut self) -> Result<(), String> {
-        let ret = match self.instructions.get(self.pc) {
-            Some(ins) => {
-                match ins {
-                    // special instructions
-                    Instruction::NOOP() => Ok(true),
-
-                    // load instructions
-                    Instruction::LOAD(x) => LOAD(self, *x),
-                    Instruction::R2A_LOAD(reg) => R2A_LOAD(self, *reg),
-                    Instruction::M2R_LOAD(mem_addr, reg) => M2R_LOAD(self, *mem_addr, *reg),
-                    Instruction::M2A_LOAD(mem_addr) => M2A_LOAD(self, *mem_addr),
-
-                    // store instructions
-                    Instruction::A2R_STORE(reg) => A2R_STORE(self, *reg),
-                    Instruction::A2M_STORE(mem_addr) => A2M_STORE(self, *mem_addr),
-                    Instruction::R2M_STORE(reg, mem_addr) => R2M_STORE(self, *reg, *mem_addr),
-
-                    // maths instructions
-                    Instruction::I_ADD(x) => I_ADD(self, *x),
-                    Instruction::R_ADD(x) => R_ADD(self, *x),
+    /// Creates an interpreter for the program's machine with the program's
+    /// data already in memory. The data must fit in memory, which the parser
+    /// checks.
+    pub fn from_program(program: Program) -> Interpreter {
+        let mut interpreter = Interpreter::with_config(program.instructions, program.machine);
+        interpreter.source_map = program.source_map;
+        for block in program.data.iter() {
+            interpreter.memory[block.address..block.address + block.words.len()]
+                .copy_from_slice(&block.words);
+        }
+        return interpreter;
+    }
 
-                    // jump instructions
-                    Instruction::JUMP(ins) => JUMP(self, *ins),
-                    Instruction::JUMP_NEG(ins) => JUMP_NEG(self, *ins),
-                }
-            },
-            None => {
-                return Err(format!("Attempted to execute instruction at idx {}, but that is out of bounds!", self.pc));
-            },
+    pub fn run_single(&mut self) -> Result<(), RuntimeError> {
+        let ret = match self.instructions.get(self.pc).copied() {
+            Some(ins) => ins.execute(self),
+            None => return Err(RuntimeError::PcOutOfRange { pc: self.pc, len: self.instructions.len() }),
         };
 
-        if ret.is_ok() {
-            if *ret.as_ref().unwrap() {
-                self.pc += 1;
-            }
-        }
         match ret {
-            Ok(_) => return Ok(()),
+            Ok(increase_pc) => {
+                if increase_pc {
+                    self.pc += 1;
+                }
+                return Ok(());
+            },
             Err(err) => Err(err),
         }
     }
 
-    pub fn run_program(&mut self) -> Result<i32, String> {
+    // the instruction at `idx`, as written in the source if we know it
+    fn describe(&self, idx: usize) -> String {
+        return match self.source_map.get(idx) {
+            Some(location) => location.to_string(),
+            None => self.instructions[idx].to_string(),
+        };
+    }
+
+    /// A message for an error from this interpreter, which says where the
+    /// instruction that faulted was written if that's known.
+    pub fn explain(&self, err: &RuntimeError) -> String {
+        return match err.instruction() {
+            Some(_) => format!("Error occurred processing instruction {}:\n{}", self.describe(err.pc()), err),
+            None => err.to_string(),
+        };
+    }
+
+    pub fn run_program(&mut self) -> Result<i32, RuntimeError> {
         while self.pc < self.instructions.len() {
+            let pc = self.pc;
+            self.run_single()?;
             if self.trace {
-                let instruction_str = self.instructions[self.pc].to_string();
-                let result = self.run_single();
-                if result.is_err() {
-                    return Err(format!("Error occurred processing instruction {}:\n{}", self.instructions[self.pc], result.unwrap_err()));
-                }
-                println!("Accumulator has value {} after instruction {}", self.accumulator, instruction_str);
-            }
-            else {
-                let result = self.run_single();
-                if result.is_err() {
-                    return Err(result.unwrap_err());
-                }
+                println!("Accumulator has value {} and flags {} after instruction {}",
+                         self.accumulator, self.flags, self.describe(pc));
             }
         }
         return Ok(self.accumulator);
     }
+
+    // the instruction being run
+    fn current(&self) -> Instruction {
+        return self.instructions[self.pc];
+    }
+
+    // checks a register operand, giving its index
+    fn register(&self, reg: i32) -> Result<usize, RuntimeError> {
+        if reg < 0 || reg as usize >= self.registers.len() {
+            return Err(RuntimeError::BadRegister { pc: self.pc, instruction: self.current(), register: reg,
+                                                   count: self.registers.len() });
+        }
+        return Ok(reg as usize);
+    }
+
+    // checks a memory address operand, giving its index
+    fn address(&self, mem_addr: i32) -> Result<usize, RuntimeError> {
+        if mem_addr < 0 || mem_addr as usize >= self.memory.len() {
+            return Err(RuntimeError::MemoryOutOfBounds { pc: self.pc, instruction: self.current(),
+                                                         address: mem_addr, size: self.memory.len() });
+        }
+        return Ok(mem_addr as usize);
+    }
+
+    // checks the target of a jump, giving the new PC
+    fn target(&self, x: i32) -> Result<usize, RuntimeError> {
+        if x < 0 || x >= self.instructions.len() as i32 {
+            return Err(RuntimeError::BadJumpTarget { pc: self.pc, instruction: self.current(), target: x,
+                                                     len: self.instructions.len() });
+        }
+        return Ok(x as usize);
+    }
+
+    // sets the accumulator to something other than the result of arithmetic
+    fn set_accumulator(&mut self, value: i32) {
+        self.accumulator = value;
+        self.flags = Flags::of(value);
+    }
+
+    // sets the flags for `op` of the accumulator and `x`, giving the exact
+    // result
+    fn compute(&mut self, x: i32, op: fn(i128, i128) -> i128) -> i64 {
+        // every operation on two i32s fits in an i64
+        let result = op(self.accumulator as i128, x as i128) as i64;
+        let unsigned = op(self.accumulator as u32 as i128, x as u32 as i128);
+        self.flags = Flags {
+            carry: u32::try_from(unsigned).is_err(),
+            overflow: i32::try_from(result).is_err(),
+            ..Flags::of(result as i32)
+        };
+        return result;
+    }
+
+    // sets the accumulator to `op` of it and `x`, fitting the result into
+    // 32 bits as the machine says to
+    fn apply(&mut self, x: i32, op: fn(i128, i128) -> i128) -> Result<(), RuntimeError> {
+        let result = self.compute(x, op);
+        self.accumulator = match self.overflow_mode {
+            _ if !self.flags.overflow => result as i32,
+            OverflowMode::Wrapping => result as i32,
+            OverflowMode::Saturating => result.clamp(i32::MIN as i64, i32::MAX as i64) as i32,
+            OverflowMode::Trapping => {
+                return Err(RuntimeError::Overflow { pc: self.pc, instruction: self.current(), result });
+            },
+        };
+        // a saturated result has a different sign to the wrapped one
+        self.flags = Flags { carry: self.flags.carry, overflow: self.flags.overflow, ..Flags::of(self.accumulator) };
+        return Ok(());
+    }
+
+    // jumps to `x` if `condition` holds, checking the target either way
+    fn jump_if(&mut self, x: i32, condition: bool) -> InstructionReturn {
+        let target = self.target(x)?;
+        if !condition {
+            return Ok(true);
+        }
+        self.pc = target;
+        return Ok(false);
+    }
+
+    // checks the divisor of a division, which can't be zero
+    fn divisor(&self, x: i32) -> Result<i32, RuntimeError> {
+        if x == 0 {
+            return Err(RuntimeError::DivisionByZero { pc: self.pc, instruction: self.current(),
+                                                      dividend: self.accumulator });
+        }
+        return Ok(x);
+    }
+
+    fn read_register(&self, reg: i32) -> Result<i32, RuntimeError> {
+        return Ok(self.registers[self.register(reg)?]);
+    }
 }
 
+// the handlers for each instruction, see the table in isa.rs
+
+fn NOOP(_: &mut Interpreter) -> InstructionReturn {
+    return Ok(true);
+}
 
 fn LOAD(s: &mut Interpreter, x: i32) -> InstructionReturn {
-    s.accumulator = x;
+    s.set_accumulator(x);
     return Ok(true);
 }
 
-fn R2A_LOAD(s: &mut Interpreter, x: i32) -> InstructionReturn {
-    if x < 0 {
-        return Err(format!("Illegal register access! Attempted to access {} but negative indices not allowed", x));
-    }
-    else if x < REG_NUMBER as i32 {
-        s.accumulator = s.registers[x as usize];
-        return Ok(true);
-    } else {
-        return Err(format!("Illegal register access! Attempted to access {} but there is only {} registers", x, REG_NUMBER));
-    }
+fn R2A_LOAD(s: &mut Interpreter, reg: i32) -> InstructionReturn {
+    s.set_accumulator(s.read_register(reg)?);
+    return Ok(true);
 }
 
 fn M2R_LOAD(s: &mut Interpreter, mem_addr: i32, reg: i32) -> InstructionReturn {
-    if mem_addr < 0 || mem_addr >= MEM_SIZE as i32 {
-        return Err(format!("Attempted to access memory out of bounds! Accessed {} but the mem size is {}", mem_addr, MEM_SIZE))
-    }
-    if reg < 0 || reg >= REG_NUMBER as i32 {
-        return Err(format!("Attempted to access bad register! Accessed {} but the register amount is {}", reg, REG_NUMBER))
-    }
-
-    s.registers[reg as usize] = s.memory[mem_addr as usize];
+    let (mem_addr, reg) = (s.address(mem_addr)?, s.register(reg)?);
+    s.registers[reg] = s.memory[mem_addr];
     return Ok(true);
 }
 
 fn M2A_LOAD(s: &mut Interpreter, mem_addr: i32) -> InstructionReturn {
-    if mem_addr < 0 || mem_addr >= MEM_SIZE as i32 {
-        return Err(format!("Attempted to access memory out of bounds! Accessed {} but the mem size is {}", mem_addr, MEM_SIZE))
-    }
-    s.accumulator = s.memory[mem_addr as usize];
-
+    s.set_accumulator(s.memory[s.address(mem_addr)?]);
     return Ok(true);
 }
 
 fn A2R_STORE(s: &mut Interpreter, reg: i32) -> InstructionReturn {
-    if reg < 0 || reg >= REG_NUMBER as i32 {
-        return Err(format!("Attempted to access bad register! Accessed {} but the register amount is {}", reg, REG_NUMBER));
-    }
-    s.registers[reg as usize] = s.accumulator;
+    let reg = s.register(reg)?;
+    s.registers[reg] = s.accumulator;
     return Ok(true)
 }
 
 fn A2M_STORE(s: &mut Interpreter, mem_addr: i32) -> InstructionReturn {
-    if mem_addr < 0 || mem_addr >= MEM_SIZE as i32 {
-        return Err(format!("Attempted to access memory out of bounds! Accessed {} but the mem size is {}", mem_addr, MEM_SIZE))
-    }
-    s.memory[mem_addr as usize] = s.accumulator;
-
+    let mem_addr = s.address(mem_addr)?;
+    s.memory[mem_addr] = s.accumulator;
     return Ok(true);
 }
 
 fn R2M_STORE(s: &mut Interpreter, reg: i32, mem_addr: i32) -> InstructionReturn {
-    if mem_addr < 0 || mem_addr >= MEM_SIZE as i32 {
-        return Err(format!("Attempted to access memory out of bounds! Accessed {} but the mem size is {}", mem_addr, MEM_SIZE));
-    }
-    if reg < 0 || reg >= REG_NUMBER as i32 {
-        return Err(format!("Attempted to access bad register! Accessed {} but the register amount is {}", reg, REG_NUMBER))
-    }
-    s.memory[mem_addr as usize] = s.registers[reg as usize];
-
+    let (mem_addr, reg) = (s.address(mem_addr)?, s.register(reg)?);
+    s.memory[mem_addr] = s.registers[reg];
     return Ok(true);
 }
 
 fn I_ADD(s: &mut Interpreter, x: i32) -> InstructionReturn {
-    s.accumulator += x;
+    s.apply(x, |a, b| a + b)?;
     return Ok(true);
 }
 
 fn R_ADD(s: &mut Interpreter, reg: i32) -> InstructionReturn {
-    if reg < 0 || reg >= REG_NUMBER as i32 {
-        return Err(format!("Attempted to access bad register! Accessed {} but the register amount is {}", reg, REG_NUMBER))
-    }
-    s.accumulator += s.registers[reg as usize];
+    return I_ADD(s, s.read_register(reg)?);
+}
+
+fn I_SUB(s: &mut Interpreter, x: i32) -> InstructionReturn {
+    s.apply(x, |a, b| a - b)?;
     return Ok(true);
 }
 
-fn JUMP(s: &mut Interpreter, x: i32) -> InstructionReturn {
-    if x < 0 {
-        return Err(format!("Illegal jump action. Tried to jump to {}", x));
-    }
-    if x >= s.instructions.len() as i32 {
-        // illegal jump
-        return Err(format!("Illegal jump action. Tried to jump from {} to {} but the last instruction has an idx of {}", s.pc, x, s.instructions.len()));
-    }
+fn R_SUB(s: &mut Interpreter, reg: i32) -> InstructionReturn {
+    return I_SUB(s, s.read_register(reg)?);
+}
+
+fn I_MUL(s: &mut Interpreter, x: i32) -> InstructionReturn {
+    s.apply(x, |a, b| a * b)?;
+    return Ok(true);
+}
+
+fn R_MUL(s: &mut Interpreter, reg: i32) -> InstructionReturn {
+    return I_MUL(s, s.read_register(reg)?);
+}
+
+fn I_DIV(s: &mut Interpreter, x: i32) -> InstructionReturn {
+    // only i32::MIN / -1 can overflow
+    s.apply(s.divisor(x)?, |a, b| a / b)?;
+    return Ok(true);
+}
+
+fn R_DIV(s: &mut Interpreter, reg: i32) -> InstructionReturn {
+    return I_DIV(s, s.read_register(reg)?);
+}
+
+fn I_MOD(s: &mut Interpreter, x: i32) -> InstructionReturn {
+    s.apply(s.divisor(x)?, |a, b| a % b)?;
+    return Ok(true);
+}
+
+fn R_MOD(s: &mut Interpreter, reg: i32) -> InstructionReturn {
+    return I_MOD(s, s.read_register(reg)?);
+}
+
+fn NEG(s: &mut Interpreter) -> InstructionReturn {
+    s.apply(0, |a, _| -a)?;
+    return Ok(true);
+}
+
+fn I_AND(s: &mut Interpreter, x: i32) -> InstructionReturn {
+    s.set_accumulator(s.accumulator & x);
+    return Ok(true);
+}
+
+fn R_AND(s: &mut Interpreter, reg: i32) -> InstructionReturn {
+    return I_AND(s, s.read_register(reg)?);
+}
+
+fn I_OR(s: &mut Interpreter, x: i32) -> InstructionReturn {
+    s.set_accumulator(s.accumulator | x);
+    return Ok(true);
+}
+
+fn R_OR(s: &mut Interpreter, reg: i32) -> InstructionReturn {
+    return I_OR(s, s.read_register(reg)?);
+}
+
+fn I_XOR(s: &mut Interpreter, x: i32) -> InstructionReturn {
+    s.set_accumulator(s.accumulator ^ x);
+    return Ok(true);
+}
+
+fn R_XOR(s: &mut Interpreter, reg: i32) -> InstructionReturn {
+    return I_XOR(s, s.read_register(reg)?);
+}
+
+fn NOT(s: &mut Interpreter) -> InstructionReturn {
+    s.set_accumulator(!s.accumulator);
+    return Ok(true);
+}
+
+// the amount to shift by, None if it would shift every bit out
+fn shift_amount(x: i32) -> Option<u32> {
+    return u32::try_from(x).ok().filter(|x| *x < i32::BITS);
+}
 
-    s.pc = x as usize;
+fn I_SHL(s: &mut Interpreter, x: i32) -> InstructionReturn {
+    s.set_accumulator(shift_amount(x).map_or(0, |x| s.accumulator << x));
+    return Ok(true);
+}
+
+fn R_SHL(s: &mut Interpreter, reg: i32) -> InstructionReturn {
+    return I_SHL(s, s.read_register(reg)?);
+}
+
+fn I_SHR(s: &mut Interpreter, x: i32) -> InstructionReturn {
+    s.set_accumulator(shift_amount(x).map_or(0, |x| ((s.accumulator as u32) >> x) as i32));
+    return Ok(true);
+}
+
+fn R_SHR(s: &mut Interpreter, reg: i32) -> InstructionReturn {
+    return I_SHR(s, s.read_register(reg)?);
+}
+
+fn I_SAR(s: &mut Interpreter, x: i32) -> InstructionReturn {
+    // shifting by 31 already fills every bit with the sign
+    s.set_accumulator(s.accumulator >> shift_amount(x).unwrap_or(i32::BITS - 1));
+    return Ok(true);
+}
+
+fn R_SAR(s: &mut Interpreter, reg: i32) -> InstructionReturn {
+    return I_SAR(s, s.read_register(reg)?);
+}
+
+fn JUMP(s: &mut Interpreter, x: i32) -> InstructionReturn {
+    s.pc = s.target(x)?;
     return Ok(false)
 }
 
 fn JUMP_NEG(s: &mut Interpreter, x: i32) -> InstructionReturn {
-    if x < 0 {
-        return Err(format!("Illegal jump action. Tried to jump to {}", x));
-    }
-    if x >= s.instructions.len() as i32 {
-        // illegal jump
-        return Err(format!("Illegal jump action. Tried to jump from {} to {} but the last instruction has an idx of {}", s.pc, x, s.instructions.len()));
-    }
-    if s.accumulator >= 0 {
-        return Ok(true)
-    }
+    return s.jump_if(x, s.accumulator < 0);
+}
 
-    s.pc = x as usize;
-    return Ok(false)
+fn CMP(s: &mut Interpreter, x: i32) -> InstructionReturn {
+    s.compute(x, |a, b| a - b);
+    return Ok(true);
+}
+
+fn R_CMP(s: &mut Interpreter, reg: i32) -> InstructionReturn {
+    return CMP(s, s.read_register(reg)?);
+}
+
+fn JUMP_ZERO(s: &mut Interpreter, x: i32) -> InstructionReturn {
+    return s.jump_if(x, s.flags.zero);
+}
+
+fn JUMP_NZ(s: &mut Interpreter, x: i32) -> InstructionReturn {
+    return s.jump_if(x, !s.flags.zero);
+}
+
+fn JUMP_POS(s: &mut Interpreter, x: i32) -> InstructionReturn {
+    return s.jump_if(x, !s.flags.zero && !s.flags.negative);
+}
+
+fn JUMP_MI(s: &mut Interpreter, x: i32) -> InstructionReturn {
+    return s.jump_if(x, s.flags.negative);
+}
+
+fn JUMP_GT(s: &mut Interpreter, x: i32) -> InstructionReturn {
+    return s.jump_if(x, !s.flags.zero && s.flags.negative == s.flags.overflow);
+}
+
+fn JUMP_GE(s: &mut Interpreter, x: i32) -> InstructionReturn {
+    return s.jump_if(x, s.flags.negative == s.flags.overflow);
+}
+
+fn JUMP_LT(s: &mut Interpreter, x: i32) -> InstructionReturn {
+    return s.jump_if(x, s.flags.negative != s.flags.overflow);
+}
+
+fn JUMP_LE(s: &mut Interpreter, x: i32) -> InstructionReturn {
+    return s.jump_if(x, s.flags.zero || s.flags.negative != s.flags.overflow);
+}
+
+fn JUMP_CARRY(s: &mut Interpreter, x: i32) -> InstructionReturn {
+    return s.jump_if(x, s.flags.carry);
+}
+
+fn JUMP_NC(s: &mut Interpreter, x: i32) -> InstructionReturn {
+    return s.jump_if(x, !s.flags.carry);
+}
+
+fn JUMP_OVF(s: &mut Interpreter, x: i32) -> InstructionReturn {
+    return s.jump_if(x, s.flags.overflow);
+}
+
+fn JUMP_NOVF(s: &mut Interpreter, x: i32) -> InstructionReturn {
+    return s.jump_if(x, !s.flags.overflow);
 }
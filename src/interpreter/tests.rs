@@ -104,3 +104,305 @@ fn jump_neg_err_test() {
     assert!(state.run_single().is_err());
 }
 
+
+#[test]
+fn from_program_test() {
+    let program = Program {
+        instructions: vec![Instruction::M2A_LOAD(3)],
+        data: vec![DataBlock { address: 2, words: vec![7, 8] }],
+        source_map: vec![],
+        machine: MachineConfig::default(),
+    };
+    let mut state = Interpreter::from_program(program);
+    assert_eq!(state.run_single(), Ok(()));
+    assert_eq!(state.accumulator, 8);
+    assert_eq!(state.memory[1], 0);
+}
+
+#[test]
+fn source_map_error_test() {
+    let location = |line, text: &str| SourceLocation { file: "prog.aaaasm".to_string(), line, text: text.to_string() };
+    let program = Program {
+        instructions: vec![Instruction::LOAD(1), Instruction::A2M_STORE(2000), Instruction::JUMP(7)],
+        data: vec![],
+        source_map: vec![location(2, "LOAD 1"), location(4, "A2M_STORE BUF :) oops"), location(5, "JUMP 7")],
+        machine: MachineConfig::default(),
+    };
+
+    let mut state = Interpreter::from_program(program.clone());
+    let err = state.run_program().unwrap_err();
+    assert_eq!(state.explain(&err), "Error occurred processing instruction `A2M_STORE BUF :) oops` \
+                                     at prog.aaaasm:4:\n\
+                                     Attempted to access memory out of bounds! Accessed 2000 but the mem size is 1024");
+
+    let mut state = Interpreter::from_program(program);
+    state.pc = 2;
+    let err = state.run_single().unwrap_err();
+    assert_eq!(state.explain(&err), "Error occurred processing instruction `JUMP 7` at prog.aaaasm:5:\n\
+                                     Illegal jump action. Tried to jump to 7 but there are only 3 instructions");
+}
+
+#[test]
+fn instruction_table_test() {
+    for info in INSTRUCTIONS.iter() {
+        let operands: Vec<i32> = (1..=info.operands.len() as i32).collect();
+        let ins = Instruction::from_parts(info.mnemonic, &operands).unwrap();
+        assert_eq!(ins.info(), info);
+        assert_eq!(ins.operands(), operands);
+    }
+    assert_eq!(Instruction::from_parts("LOAD", &[]), None);
+    assert_eq!(Instruction::from_parts("LOAD", &[1, 2]), None);
+    assert_eq!(Instruction::from_parts("load", &[1]), None);
+}
+
+#[test]
+fn instruction_display_test() {
+    assert_eq!(Instruction::NOOP().to_string(), "NOOP");
+    assert_eq!(Instruction::M2R_LOAD(12, 3).to_string(), "M2R_LOAD 12 r3");
+    assert_eq!(Instruction::JUMP_NEG(-4).to_string(), "JUMP_NEG -4");
+}
+
+#[test]
+fn isa_reference_test() {
+    let reference = isa_reference();
+    assert_eq!(reference.lines().count(), INSTRUCTIONS.len() + 2);
+    assert!(reference.contains("| `R2M_STORE` | reg (register), address (number) | Stores a register in memory. |\n"));
+}
+
+#[test]
+fn trace_jump_test() {
+    let mut state = Interpreter::new(vec![Instruction::LOAD(-2), Instruction::I_ADD(1),
+                                          Instruction::JUMP_NEG(1)]);
+    state.trace = true;
+    assert_eq!(state.run_program(), Ok(0));
+}
+
+#[test]
+fn from_program_entry_test() {
+    let program = Program {
+        instructions: vec![Instruction::LOAD(1), Instruction::I_ADD(2), Instruction::I_ADD(3)],
+        data: vec![],
+        source_map: vec![],
+        machine: MachineConfig { initial_pc: 1, ..MachineConfig::default() },
+    };
+    let mut state = Interpreter::from_program(program);
+    assert_eq!(state.run_program(), Ok(5));
+}
+
+#[test]
+fn runtime_error_test() {
+    let mut state = Interpreter::new(vec![Instruction::R2A_LOAD(-1)]);
+    assert_eq!(state.run_single(), Err(RuntimeError::BadRegister {
+        pc: 0, instruction: Instruction::R2A_LOAD(-1), register: -1, count: REG_NUMBER,
+    }));
+
+    let mut state = Interpreter::new(vec![Instruction::NOOP(), Instruction::M2R_LOAD(1024, 7)]);
+    state.pc = 1;
+    let err = state.run_program().unwrap_err();
+    assert!(matches!(err, RuntimeError::MemoryOutOfBounds { pc: 1, address: 1024, .. }));
+    assert_eq!(err.instruction(), Some(Instruction::M2R_LOAD(1024, 7)));
+    assert_eq!(state.explain(&err), "Error occurred processing instruction M2R_LOAD 1024 r7:\n\
+                                     Attempted to access memory out of bounds! Accessed 1024 but the mem size is 1024");
+
+    let mut state = Interpreter::new(vec![Instruction::LOAD(i32::MAX), Instruction::I_ADD(1)]);
+    let err = state.run_program().unwrap_err();
+    assert_eq!(err, RuntimeError::Overflow { pc: 1, instruction: Instruction::I_ADD(1), result: 1 << 31 });
+    assert_eq!(err.to_string(), "Overflow! The result 2147483648 does not fit in 32 bits");
+    assert_eq!(state.accumulator, i32::MAX);
+
+    let mut state = Interpreter::new(vec![]);
+    state.pc = 3;
+    let err = state.run_single().unwrap_err();
+    assert_eq!((err.pc(), err.instruction()), (3, None));
+    assert_eq!(state.explain(&err), "Attempted to execute instruction at idx 3, but there are only 0 instructions");
+}
+
+#[test]
+fn machine_config_test() {
+    let config = MachineConfig { registers: 8, memory: 16, initial_pc: 1, ..MachineConfig::default() };
+    let mut state = Interpreter::with_config(vec![Instruction::LOAD(1), Instruction::LOAD(2),
+                                                  Instruction::A2R_STORE(7), Instruction::A2M_STORE(15),
+                                                  Instruction::A2M_STORE(16)], config);
+    assert!(matches!(state.run_program(), Err(RuntimeError::MemoryOutOfBounds { pc: 4, address: 16, size: 16, .. })));
+    assert_eq!((state.registers[7], state.memory[15]), (2, 2));
+    assert_eq!(state.accumulator, 2);
+}
+
+#[test]
+fn overflow_mode_test() {
+    let program = vec![Instruction::LOAD(i32::MAX - 1), Instruction::I_ADD(3), Instruction::R_ADD(0)];
+    let run = |overflow| {
+        let mut state = Interpreter::with_config(program.clone(), MachineConfig { overflow, ..MachineConfig::default() });
+        let result = state.run_program();
+        return (result, state.flags.overflow);
+    };
+    assert_eq!(run(OverflowMode::Wrapping), (Ok(i32::MIN + 1), false));
+    assert_eq!(run(OverflowMode::Saturating), (Ok(i32::MAX), false));
+    assert!(run(OverflowMode::Trapping).1);
+    assert!(matches!(run(OverflowMode::Trapping).0, Err(RuntimeError::Overflow { pc: 1, .. })));
+
+    let mut state = Interpreter::with_config(vec![Instruction::LOAD(i32::MIN), Instruction::I_ADD(-1)],
+                                             MachineConfig { overflow: OverflowMode::Saturating, ..MachineConfig::default() });
+    assert_eq!(state.run_program(), Ok(i32::MIN));
+    assert!(state.flags.overflow);
+
+    assert_eq!("wrapping".parse(), Ok(OverflowMode::Wrapping));
+    assert!("wrap".parse::<OverflowMode>().is_err());
+}
+
+#[test]
+fn arithmetic_test() {
+    let run = |ins: Vec<Instruction>| {
+        let mut state = Interpreter::new(ins);
+        state.registers[1] = -3;
+        return state.run_program();
+    };
+    assert_eq!(run(vec![Instruction::LOAD(10), Instruction::I_SUB(4), Instruction::R_SUB(1)]), Ok(9));
+    assert_eq!(run(vec![Instruction::LOAD(10), Instruction::I_MUL(4), Instruction::R_MUL(1)]), Ok(-120));
+    assert_eq!(run(vec![Instruction::LOAD(-7), Instruction::I_DIV(2)]), Ok(-3));
+    assert_eq!(run(vec![Instruction::LOAD(7), Instruction::R_DIV(1)]), Ok(-2));
+    assert_eq!(run(vec![Instruction::LOAD(-7), Instruction::I_MOD(2)]), Ok(-1));
+    assert_eq!(run(vec![Instruction::LOAD(7), Instruction::R_MOD(1)]), Ok(1));
+    assert_eq!(run(vec![Instruction::LOAD(7), Instruction::NEG()]), Ok(-7));
+
+    assert_eq!(run(vec![Instruction::LOAD(7), Instruction::I_DIV(0)]),
+               Err(RuntimeError::DivisionByZero { pc: 1, instruction: Instruction::I_DIV(0), dividend: 7 }));
+    let err = run(vec![Instruction::R_MOD(2)]).unwrap_err();
+    assert_eq!(err.to_string(), "Division by zero! Attempted to divide 0 by 0");
+
+    assert!(matches!(run(vec![Instruction::LOAD(i32::MIN), Instruction::NEG()]),
+                     Err(RuntimeError::Overflow { result: 2147483648, .. })));
+    assert!(matches!(run(vec![Instruction::LOAD(i32::MIN), Instruction::I_DIV(-1)]),
+                     Err(RuntimeError::Overflow { .. })));
+    assert_eq!(run(vec![Instruction::LOAD(i32::MIN), Instruction::I_MOD(-1)]), Ok(0));
+    assert!(matches!(run(vec![Instruction::LOAD(1 << 16), Instruction::I_MUL(1 << 16)]),
+                     Err(RuntimeError::Overflow { result: 4294967296, .. })));
+}
+
+#[test]
+fn bitwise_test() {
+    let run = |ins: Vec<Instruction>| {
+        let mut state = Interpreter::new(ins);
+        state.registers[1] = 0b0110;
+        return state.run_program();
+    };
+    assert_eq!(run(vec![Instruction::LOAD(0b1100), Instruction::I_AND(0b1010)]), Ok(0b1000));
+    assert_eq!(run(vec![Instruction::LOAD(0b1100), Instruction::R_AND(1)]), Ok(0b0100));
+    assert_eq!(run(vec![Instruction::LOAD(0b1100), Instruction::I_OR(0b1010)]), Ok(0b1110));
+    assert_eq!(run(vec![Instruction::LOAD(0b1100), Instruction::R_OR(1)]), Ok(0b1110));
+    assert_eq!(run(vec![Instruction::LOAD(0b1100), Instruction::I_XOR(0b1010)]), Ok(0b0110));
+    assert_eq!(run(vec![Instruction::LOAD(0b1100), Instruction::R_XOR(1)]), Ok(0b1010));
+    assert_eq!(run(vec![Instruction::LOAD(0), Instruction::NOT()]), Ok(-1));
+    assert_eq!(run(vec![Instruction::LOAD(5), Instruction::NOT()]), Ok(-6));
+    assert_eq!(run(vec![Instruction::R_AND(4)]),
+               Err(RuntimeError::BadRegister { pc: 0, instruction: Instruction::R_AND(4), register: 4, count: 4 }));
+}
+
+#[test]
+fn shift_test() {
+    let run = |value, ins: Instruction| {
+        let mut state = Interpreter::new(vec![Instruction::LOAD(value), ins]);
+        state.registers[1] = 6;
+        state.registers[2] = 40;
+        return state.run_program();
+    };
+    assert_eq!(run(3, Instruction::I_SHL(4)), Ok(48));
+    assert_eq!(run(3, Instruction::R_SHL(1)), Ok(192));
+    assert_eq!(run(1, Instruction::I_SHL(31)), Ok(i32::MIN));
+    assert_eq!(run(-1, Instruction::I_SHR(28)), Ok(0xF));
+    assert_eq!(run(-64, Instruction::R_SHR(1)), Ok(0x03FF_FFFF));
+    assert_eq!(run(-64, Instruction::I_SAR(3)), Ok(-8));
+    assert_eq!(run(64, Instruction::R_SAR(1)), Ok(1));
+
+    // shifting by 32 or more, or by a negative amount, shifts every bit out
+    assert_eq!(run(-1, Instruction::I_SHL(32)), Ok(0));
+    assert_eq!(run(-1, Instruction::R_SHR(2)), Ok(0));
+    assert_eq!(run(-1, Instruction::I_SHR(-1)), Ok(0));
+    assert_eq!(run(-5, Instruction::R_SAR(2)), Ok(-1));
+    assert_eq!(run(5, Instruction::I_SAR(-3)), Ok(0));
+    assert_eq!(run(5, Instruction::I_SHL(0)), Ok(5));
+}
+
+#[test]
+fn flags_test() {
+    let flags = |ins: Vec<Instruction>| {
+        let mut state = Interpreter::with_config(ins, MachineConfig { overflow: OverflowMode::Wrapping,
+                                                                      ..MachineConfig::default() });
+        state.run_program().unwrap();
+        return state.flags.to_string();
+    };
+    assert_eq!(flags(vec![Instruction::LOAD(0)]), "Z---");
+    assert_eq!(flags(vec![Instruction::LOAD(-1), Instruction::I_ADD(1)]), "Z-C-");
+    assert_eq!(flags(vec![Instruction::LOAD(i32::MAX), Instruction::I_ADD(1)]), "-N-V");
+    assert_eq!(flags(vec![Instruction::LOAD(1), Instruction::I_SUB(2)]), "-NC-");
+    assert_eq!(flags(vec![Instruction::LOAD(i32::MIN), Instruction::I_SUB(1), Instruction::I_AND(0)]), "Z---");
+    assert_eq!(flags(vec![Instruction::LOAD(1 << 16), Instruction::I_MUL(1 << 16)]), "Z-CV");
+    assert_eq!(flags(vec![Instruction::LOAD(3), Instruction::NEG()]), "-NC-");
+    assert_eq!(flags(vec![Instruction::LOAD(i32::MIN), Instruction::I_DIV(-1)]), "-N-V");
+    assert_eq!(flags(vec![Instruction::A2R_STORE(0)]), "----");
+
+    // zero and negative come from the saturated accumulator, so JUMP_POS
+    // agrees with JUMP_NEG
+    let mut state = Interpreter::with_config(vec![Instruction::LOAD(i32::MAX), Instruction::I_ADD(1),
+                                                  Instruction::JUMP_POS(4), Instruction::LOAD(0), Instruction::NOOP()],
+                                             MachineConfig { overflow: OverflowMode::Saturating,
+                                                             ..MachineConfig::default() });
+    assert_eq!(state.run_program(), Ok(i32::MAX));
+    assert_eq!(state.flags, Flags { zero: false, negative: false, carry: false, overflow: true });
+
+    let mut state = Interpreter::with_config(vec![Instruction::LOAD(i32::MIN), Instruction::I_ADD(i32::MIN)],
+                                             MachineConfig { overflow: OverflowMode::Saturating,
+                                                             ..MachineConfig::default() });
+    assert_eq!(state.run_program(), Ok(i32::MIN));
+    assert_eq!(state.flags.to_string(), "-NCV");
+}
+
+#[test]
+fn cmp_test() {
+    let mut state = Interpreter::new(vec![Instruction::LOAD(5), Instruction::CMP(5), Instruction::R_CMP(1)]);
+    state.registers[1] = 7;
+    assert_eq!(state.run_single(), Ok(()));
+    assert_eq!(state.run_single(), Ok(()));
+    assert_eq!((state.accumulator, state.flags.zero), (5, true));
+    assert_eq!(state.run_single(), Ok(()));
+    assert_eq!((state.accumulator, state.flags.to_string().as_str()), (5, "-NC-"));
+
+    // comparing never traps, even when the subtraction would overflow
+    let mut state = Interpreter::new(vec![Instruction::LOAD(i32::MIN), Instruction::CMP(1)]);
+    assert_eq!(state.run_program(), Ok(i32::MIN));
+    assert_eq!(state.flags.to_string(), "---V");
+}
+
+#[test]
+fn conditional_jump_test() {
+    // whether each jump is taken after comparing `a` with `b`
+    let taken = |a, b, jump: fn(i32) -> Instruction| {
+        let mut state = Interpreter::new(vec![Instruction::LOAD(a), Instruction::CMP(b), jump(5),
+                                              Instruction::NOOP(), Instruction::NOOP(), Instruction::NOOP()]);
+        for _ in 0..3 {
+            assert_eq!(state.run_single(), Ok(()));
+        }
+        return state.pc == 5;
+    };
+    let pairs = [(1, 2), (2, 2), (3, 2), (i32::MIN, 1), (i32::MAX, -1), (-1, 1)];
+    for (a, b) in pairs {
+        assert_eq!(taken(a, b, Instruction::JUMP_ZERO), a == b);
+        assert_eq!(taken(a, b, Instruction::JUMP_NZ), a != b);
+        assert_eq!(taken(a, b, Instruction::JUMP_GT), a > b);
+        assert_eq!(taken(a, b, Instruction::JUMP_GE), a >= b);
+        assert_eq!(taken(a, b, Instruction::JUMP_LT), a < b);
+        assert_eq!(taken(a, b, Instruction::JUMP_LE), a <= b);
+        assert_eq!(taken(a, b, Instruction::JUMP_CARRY), (a as u32) < (b as u32));
+        assert_eq!(taken(a, b, Instruction::JUMP_NC), (a as u32) >= (b as u32));
+        assert_eq!(taken(a, b, Instruction::JUMP_OVF), (a as i64 - b as i64) != (a.wrapping_sub(b) as i64));
+        assert_eq!(taken(a, b, Instruction::JUMP_NOVF), (a as i64 - b as i64) == (a.wrapping_sub(b) as i64));
+    }
+
+    assert!(taken(3, 0, Instruction::JUMP_POS));
+    assert!(!taken(0, 0, Instruction::JUMP_POS));
+    assert!(taken(-3, 0, Instruction::JUMP_MI));
+    assert!(!taken(3, 0, Instruction::JUMP_MI));
+
+    let mut state = Interpreter::new(vec![Instruction::JUMP_ZERO(3)]);
+    assert!(matches!(state.run_single(), Err(RuntimeError::BadJumpTarget { target: 3, .. })));
+}
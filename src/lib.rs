@@ -0,0 +1,4 @@
+#![allow(clippy::needless_return)]
+#![allow(clippy::upper_case_acronyms)]
+pub mod interpreter;
+pub mod parser;
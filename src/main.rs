@@ -1,49 +1,80 @@
-mod interpreter;
-mod parser;
+#![allow(clippy::needless_return)]
+#![allow(clippy::upper_case_acronyms)]
 mod cli;
 use clap::Parser;
 
-use interpreter::Interpreter;
+use aaaasm::interpreter::{isa_reference, Interpreter};
+use aaaasm::parser;
 
 fn main() {
     let cli = cli::CLI::parse();
 
     match cli.command {
-        cli::Commands::Run {file, trace} => run(file, trace),
+        cli::Commands::Run {file, trace, define, entry, registers, memory, overflow} => {
+            run(file, trace, parser::ParseOptions { defines: define, entry, registers, memory, overflow })
+        },
+        cli::Commands::Fmt {files, check} => fmt(files, check),
+        cli::Commands::Isa => print!("{}", isa_reference()),
     };
 }
 
-fn run(file: String, trace: bool) {
-    let maybe_input = std::fs::read_to_string(file);
+fn run(file: String, trace: bool, options: parser::ParseOptions) {
+    let maybe_input = std::fs::read_to_string(&file);
     let input = match maybe_input {
         Ok(s) => s,
-        Err(err) => {eprintln!("Could not read file: {}", err); return},
+        Err(err) => {eprintln!("Could not read file: {}", err); std::process::exit(1)},
     };
 
-    let parsed_input = parser::parse_code(&input);
+    let program = match parser::parse_source_with(&file, &input, &options,
+                                                  &mut |path| std::fs::read_to_string(path)) {
+        Ok(program) => program,
+        Err(errors) => {
+            for err in errors.iter() {
+                eprintln!("{}\n", err.render());
+            }
+            eprintln!("error: could not parse {} due to {} previous error{}",
+                      file, errors.len(), if errors.len() == 1 { "" } else { "s" });
+            std::process::exit(1);
+        },
+    };
 
-    if parsed_input.is_err() {
-        eprintln!("fatal error: couldnt parse code, error: \n{},\nexiting",
-                  parsed_input.unwrap_err());
-        return
-    }
+    let mut interpreter = Interpreter::from_program(program);
 
+    interpreter.trace = trace;
 
-    let instructions = match parsed_input {
-        Ok(instructions) => instructions,
+    match interpreter.run_program() {
+        Ok(result) => println!("program finished with {} in the accumulator and flags {}",
+                               result, interpreter.flags),
         Err(err) => {
-            eprintln!("Couldn't parse instructions, error: {}", err);
-            return;
+            println!("program failed with error: {}", interpreter.explain(&err));
+            std::process::exit(1);
         },
-    };
+    }
+}
 
-    let mut interpreter = Interpreter::new(instructions);
+fn fmt(files: Vec<String>, check: bool) {
+    let mut unformatted = 0;
+    for file in files.iter() {
+        let input = match std::fs::read_to_string(file) {
+            Ok(s) => s,
+            Err(err) => {eprintln!("Could not read {}: {}", file, err); std::process::exit(1)},
+        };
 
-    interpreter.trace = trace;
+        let output = parser::format_source(&input);
+        if output == input {
+            continue;
+        }
 
-    match interpreter.run_program() {
-        Ok(result) => println!("program finished with {} in the accumulator",
-                               result),
-        Err(err) => println!("program failed with error: {}", err),
+        if check {
+            println!("{} is not formatted", file);
+            unformatted += 1;
+        } else if let Err(err) = std::fs::write(file, output) {
+            eprintln!("Could not write {}: {}", file, err);
+            std::process::exit(1);
+        }
+    }
+
+    if unformatted > 0 {
+        std::process::exit(1);
     }
 }
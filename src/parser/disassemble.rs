@@ -0,0 +1,46 @@
+// Turns instructions back into source. Jump targets become labels, named
+// L0, L1 and so on in the order they appear, so the output reads like
+// something a person could have written and parses back into the same
+// instructions.
+
+use std::collections::BTreeMap;
+
+use crate::interpreter::{Instruction, OperandKind};
+use crate::parser::format_source;
+
+pub fn disassemble(instructions: &[Instruction]) -> String {
+    // targets outside the program are left as numbers, there's nowhere to
+    // put a label for them
+    let mut labels = BTreeMap::new();
+    for ins in instructions.iter() {
+        for (value, kind) in ins.operands().iter().zip(ins.info().operands.iter()) {
+            if *kind == OperandKind::Target && *value >= 0 && (*value as usize) < instructions.len() {
+                labels.insert(*value as usize, String::new());
+            }
+        }
+    }
+    for (idx, name) in labels.values_mut().enumerate() {
+        *name = format!("L{}", idx);
+    }
+
+    let mut out = String::new();
+    for (idx, ins) in instructions.iter().enumerate() {
+        if let Some(label) = labels.get(&idx) {
+            out += &format!("{}:\n", label);
+        }
+
+        let info = ins.info();
+        out += info.mnemonic;
+        for (value, kind) in ins.operands().iter().zip(info.operands.iter()) {
+            let label = labels.get(&(*value as usize)).filter(|_| *kind == OperandKind::Target && *value >= 0);
+            match (kind, label) {
+                (OperandKind::Register, _) => out += &format!(" r{}", value),
+                (_, Some(label)) => out += &format!(" {}", label),
+                _ => out += &format!(" {}", value),
+            }
+        }
+        out.push('\n');
+    }
+
+    return format_source(&out);
+}
@@ -0,0 +1,65 @@
+// A parsed file that is kept up to date as it's edited, for editors that
+// want errors as the user types. Only the lines an edit touches are lexed
+// and parsed again, every other line keeps the statement parsed from it, and
+// included files are only read the first time they're included. The passes
+// after that, macros, labels and layout, run over the whole program each
+// time, since a change to one line, like renaming a label or adding a
+// `.macro`, can change what any other line means.
+
+use std::ops::Range;
+
+use crate::interpreter::Program;
+use crate::parser::{parse_tree, FileLoader, IncludeCache, ParseError, ParseOptions, SyntaxTree};
+
+/// A file and the result of parsing it.
+#[derive(Debug, Clone)]
+pub struct Document {
+    file_name: String,
+    options: ParseOptions,
+    tree: SyntaxTree,
+    includes: IncludeCache,
+    result: Result<Program, Vec<ParseError>>,
+}
+
+impl Document {
+    /// Parses `s` like `parse_source_with`.
+    pub fn parse(file_name: &str, s: &str, options: ParseOptions, load: &mut FileLoader) -> Document {
+        let tree = SyntaxTree::parse(file_name, s);
+        let mut includes = IncludeCache::default();
+        let result = parse_tree(file_name, &tree, &options, load, &mut includes);
+        return Document { file_name: file_name.to_string(), options, tree, includes, result };
+    }
+
+    /// Replaces the bytes in `range` with `replacement` and parses the
+    /// program again, with the same result as parsing the new text from
+    /// scratch. Only files that haven't been included before are read with
+    /// `load`. Returns the indices of the lines that were parsed again, like
+    /// `SyntaxTree::edit`.
+    ///
+    /// Panics if `range` is out of bounds or isn't on character boundaries.
+    pub fn edit(&mut self, range: Range<usize>, replacement: &str, load: &mut FileLoader) -> Range<usize> {
+        let lines = self.tree.edit(range, replacement);
+        self.result = parse_tree(&self.file_name, &self.tree, &self.options, load, &mut self.includes);
+        return lines;
+    }
+
+    /// Reads every included file again with `load` and parses the program
+    /// again, for when included files have changed.
+    pub fn reload(&mut self, load: &mut FileLoader) {
+        self.includes = IncludeCache::default();
+        self.result = parse_tree(&self.file_name, &self.tree, &self.options, load, &mut self.includes);
+    }
+
+    pub fn tree(&self) -> &SyntaxTree {
+        return &self.tree;
+    }
+
+    pub fn result(&self) -> &Result<Program, Vec<ParseError>> {
+        return &self.result;
+    }
+
+    /// The current text of the file.
+    pub fn text(&self) -> String {
+        return self.tree.to_string();
+    }
+}
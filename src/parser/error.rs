@@ -0,0 +1,224 @@
+use std::fmt;
+use std::rc::Rc;
+
+/// Where in the source a token lives. Lines are 1-indexed like an editor,
+/// columns are 0-indexed byte offsets into the line, `end` is exclusive.
+/// The text of the whole line is kept so errors can be shown without going
+/// back to the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub file: Rc<str>,
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+    pub line_text: Rc<str>,
+    // set when the token was copied out of a macro definition
+    pub expansion: Option<Rc<Expansion>>,
+}
+
+impl Span {
+    /// The span outside of any macro expansions that this one came from.
+    pub fn root(&self) -> &Span {
+        return match &self.expansion {
+            Some(expansion) => expansion.call_site.root(),
+            None => self,
+        };
+    }
+
+    /// The span from the start of this one to the end of `end`. Tokens from
+    /// a macro call and from the macro body can end up next to each other,
+    /// so if the two aren't on the same line of the same expansion this is
+    /// just `self`.
+    pub fn join(&self, end: &Span) -> Span {
+        let mut span = self.clone();
+        if end.file == self.file && end.line == self.line && end.expansion == self.expansion
+            && end.end >= self.start {
+            span.end = end.end;
+        }
+        return span;
+    }
+}
+
+/// A use of a macro, which tokens in its expansion point back to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expansion {
+    pub macro_name: String,
+    pub call_site: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    UnknownMnemonic(String),
+    MissingInstruction,
+    UnexpectedToken(String),
+    WrongOperandCount { mnemonic: String, expected: usize, found: usize },
+    WrongOperandKind { mnemonic: String, expected: &'static str, found: &'static str },
+    MalformedNumber(String),
+    NumberOutOfRange(String),
+    MalformedString(String),
+    MalformedRegister(String),
+    Expected(&'static str),
+    ExpectedExpression,
+    UnclosedParen,
+    InvalidLabel(String),
+    UnknownDirective(String),
+    UndefinedSymbol(String),
+    DuplicateSymbol { name: String, first_line: usize },
+    // clashes with a define from outside the source
+    AlreadyDefined(String),
+    AliasInExpression(String),
+    DuplicateDirective { directive: String, first_line: usize },
+    EntryOutOfRange(i32),
+    // a label in the data section used as somewhere to jump to
+    DataLabelAsTarget(String),
+    NoGlobalLabel(String),
+    MissingNumericLabel(String),
+    RegisterOutOfRange { register: i32, count: usize },
+    MachineOutOfRange { what: &'static str, value: i64, max: usize },
+    CyclicDefinition(String),
+    ExpressionOverflow,
+    DivisionByZero,
+    Unterminated { directive: String, terminator: &'static str },
+    Unmatched(String),
+    MacroRecursion(String),
+    WrongSection { what: String, section: &'static str },
+    DataOutOfRange { address: i64, len: usize },
+    FillTooLarge { count: i32, max: usize },
+    DataOverlap { address: usize, first_line: usize },
+    IncludeFailed { path: String, reason: String },
+    // every file in the cycle, starting and ending with the same one
+    IncludeCycle(Vec<String>),
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseErrorKind::UnknownMnemonic(m) =>
+                write!(f, "unknown mnemonic `{}`", m),
+            ParseErrorKind::MissingInstruction =>
+                write!(f, "expected an instruction"),
+            ParseErrorKind::UnexpectedToken(s) =>
+                write!(f, "unexpected `{}`", s),
+            ParseErrorKind::WrongOperandCount { mnemonic, expected, found } =>
+                write!(f, "`{}` takes {} operand(s) but {} were given", mnemonic, expected, found),
+            ParseErrorKind::WrongOperandKind { mnemonic, expected, found } =>
+                write!(f, "`{}` expected a {} here but found a {}", mnemonic, expected, found),
+            ParseErrorKind::MalformedNumber(s) =>
+                write!(f, "`{}` is not a valid number", s),
+            ParseErrorKind::NumberOutOfRange(s) =>
+                write!(f, "`{}` does not fit in 32 bits", s),
+            ParseErrorKind::MalformedString(s) =>
+                write!(f, "`{}` is not a valid string", s),
+            ParseErrorKind::MalformedRegister(s) =>
+                write!(f, "`{}` is not a valid register", s),
+            ParseErrorKind::Expected(what) =>
+                write!(f, "expected {}", what),
+            ParseErrorKind::ExpectedExpression =>
+                write!(f, "expected an expression"),
+            ParseErrorKind::UnclosedParen =>
+                write!(f, "this `(` is never closed"),
+            ParseErrorKind::InvalidLabel(name) =>
+                write!(f, "`{}` is not a valid name", name),
+            ParseErrorKind::UnknownDirective(name) =>
+                write!(f, "unknown directive `{}`", name),
+            ParseErrorKind::UndefinedSymbol(name) =>
+                write!(f, "`{}` is not defined", name),
+            ParseErrorKind::DuplicateSymbol { name, first_line } =>
+                write!(f, "`{}` was already defined on line {}", name, first_line),
+            ParseErrorKind::AlreadyDefined(name) =>
+                write!(f, "`{}` is already defined on the command line", name),
+            ParseErrorKind::DuplicateDirective { directive, first_line } =>
+                write!(f, "`{}` was already used on line {}", directive, first_line),
+            ParseErrorKind::EntryOutOfRange(x) =>
+                write!(f, "the program can't start at {} as there is no instruction there", x),
+            ParseErrorKind::DataLabelAsTarget(name) =>
+                write!(f, "`{}` is the address of some data, not an instruction to jump to", name),
+            ParseErrorKind::NoGlobalLabel(name) =>
+                write!(f, "`{}` is a local label, but there is no global label before it", name),
+            ParseErrorKind::MissingNumericLabel(name) if name.ends_with('f') =>
+                write!(f, "there is no `{}:` label after `{}`", &name[..name.len() - 1], name),
+            ParseErrorKind::MissingNumericLabel(name) =>
+                write!(f, "there is no `{}:` label before `{}`", &name[..name.len() - 1], name),
+            ParseErrorKind::AliasInExpression(name) =>
+                write!(f, "`{}` is a register, so it can't be used in an expression", name),
+            ParseErrorKind::RegisterOutOfRange { register, count } =>
+                write!(f, "there is no register r{}, registers go from r0 to r{}", register, *count as i32 - 1),
+            ParseErrorKind::MachineOutOfRange { what, value, max } =>
+                write!(f, "a machine can have from 0 to {} {}, not {}", max, what, value),
+            ParseErrorKind::CyclicDefinition(name) =>
+                write!(f, "`{}` is defined in terms of itself", name),
+            ParseErrorKind::ExpressionOverflow =>
+                write!(f, "expression does not fit in 32 bits"),
+            ParseErrorKind::DivisionByZero =>
+                write!(f, "division by zero in expression"),
+            ParseErrorKind::Unterminated { directive, terminator } =>
+                write!(f, "this `{}` is never closed with `{}`", directive, terminator),
+            ParseErrorKind::Unmatched(directive) =>
+                write!(f, "`{}` doesn't close anything", directive),
+            ParseErrorKind::MacroRecursion(name) =>
+                write!(f, "macro `{}` expands into itself too many times", name),
+            ParseErrorKind::WrongSection { what, section } =>
+                write!(f, "`{}` can only be used in the {} section", what, section),
+            ParseErrorKind::DataOutOfRange { address, len } =>
+                write!(f, "{} word(s) of data at address {} would not fit in memory", len, address),
+            ParseErrorKind::FillTooLarge { count, max } =>
+                write!(f, "can't fill {} words, memory is never more than {} words", count, max),
+            ParseErrorKind::DataOverlap { address, first_line } =>
+                write!(f, "data at address {} overlaps the data defined on line {}", address, first_line),
+            ParseErrorKind::IncludeFailed { path, reason } =>
+                write!(f, "could not include `{}`: {}", path, reason),
+            ParseErrorKind::IncludeCycle(chain) =>
+                write!(f, "include cycle: {}", chain.join(" -> ")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Span,
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, span: &Span) -> ParseError {
+        return ParseError { kind, span: span.clone() };
+    }
+
+    /// Renders the error rustc-style, with the source line and a caret under
+    /// the offending token. Errors inside a macro expansion also show where
+    /// the macro was used.
+    pub fn render(&self) -> String {
+        let mut out = format!("error: {}\n{}", self.kind, render_span(&self.span));
+        let mut expansion = &self.span.expansion;
+        while let Some(exp) = expansion {
+            out += &format!("\nnote: in this expansion of macro `{}`\n{}",
+                            exp.macro_name, render_span(&exp.call_site));
+            expansion = &exp.call_site.expansion;
+        }
+        return out;
+    }
+}
+
+fn render_span(span: &Span) -> String {
+    let line_num = span.line.to_string();
+    let gutter = " ".repeat(line_num.len());
+
+    // keep tabs in the padding so the carets line up with the source
+    let padding: String = span.line_text.get(..span.start).unwrap_or("")
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let carets = "^".repeat(span.end.saturating_sub(span.start).max(1));
+
+    return format!("{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}",
+                   gutter, span.file, span.line, span.start + 1,
+                   gutter,
+                   line_num, span.line_text,
+                   gutter, padding, carets);
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.span.file, self.span.line, self.span.start + 1, self.kind)
+    }
+}
@@ -0,0 +1,267 @@
+// Constant expressions in operands, like `BUF+4` or `(SIZE * 2) - 1`. They
+// are evaluated at parse time, symbols are looked up through a callback so
+// the same code works for labels, `.equ` constants and anything else that
+// has a value by the time the expression is needed.
+
+use crate::parser::lexer::{Token, TokenKind};
+use crate::parser::{literal, ParseError, ParseErrorKind, Span};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExprKind {
+    Number(i32),
+    Symbol(String),
+    Neg(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    /// Evaluates the expression. `lookup` gives the value of a symbol, it is
+    /// responsible for reporting undefined symbols and returns None if the
+    /// symbol has no usable value. Errors are pushed onto `errors`, None is
+    /// returned if there were any.
+    pub fn eval(&self, lookup: &mut dyn FnMut(&str, &Span) -> Option<i32>,
+                errors: &mut Vec<ParseError>) -> Option<i32> {
+        return match &self.kind {
+            ExprKind::Number(x) => Some(*x),
+            ExprKind::Symbol(name) => lookup(name, &self.span),
+            ExprKind::Neg(inner) => {
+                let x = inner.eval(lookup, errors)?;
+                self.checked(x.checked_neg(), ParseErrorKind::ExpressionOverflow, errors)
+            },
+            ExprKind::Binary(op, lhs, rhs) => {
+                // evaluate both sides so errors on the right aren't hidden by the left
+                let x = lhs.eval(lookup, errors);
+                let y = rhs.eval(lookup, errors);
+                let (x, y) = (x?, y?);
+                match op {
+                    BinaryOp::Add => self.checked(x.checked_add(y), ParseErrorKind::ExpressionOverflow, errors),
+                    BinaryOp::Sub => self.checked(x.checked_sub(y), ParseErrorKind::ExpressionOverflow, errors),
+                    BinaryOp::Mul => self.checked(x.checked_mul(y), ParseErrorKind::ExpressionOverflow, errors),
+                    BinaryOp::Div if y == 0 => self.checked(None, ParseErrorKind::DivisionByZero, errors),
+                    BinaryOp::Div => self.checked(x.checked_div(y), ParseErrorKind::ExpressionOverflow, errors),
+                }
+            },
+        };
+    }
+
+    /// Calls `f` on the name of every symbol in the expression.
+    pub fn symbols_mut(&mut self, f: &mut dyn FnMut(&mut String, &Span)) {
+        match &mut self.kind {
+            ExprKind::Number(_) => {},
+            ExprKind::Symbol(name) => f(name, &self.span),
+            ExprKind::Neg(inner) => inner.symbols_mut(f),
+            ExprKind::Binary(_, lhs, rhs) => {
+                lhs.symbols_mut(f);
+                rhs.symbols_mut(f);
+            },
+        }
+    }
+
+    pub fn symbols(&self, f: &mut dyn FnMut(&str, &Span)) {
+        match &self.kind {
+            ExprKind::Number(_) => {},
+            ExprKind::Symbol(name) => f(name, &self.span),
+            ExprKind::Neg(inner) => inner.symbols(f),
+            ExprKind::Binary(_, lhs, rhs) => {
+                lhs.symbols(f);
+                rhs.symbols(f);
+            },
+        }
+    }
+
+    pub fn spans_mut(&mut self, f: &mut dyn FnMut(&mut Span)) {
+        f(&mut self.span);
+        match &mut self.kind {
+            ExprKind::Number(_) | ExprKind::Symbol(_) => {},
+            ExprKind::Neg(inner) => inner.spans_mut(f),
+            ExprKind::Binary(_, lhs, rhs) => {
+                lhs.spans_mut(f);
+                rhs.spans_mut(f);
+            },
+        }
+    }
+
+    fn checked(&self, value: Option<i32>, kind: ParseErrorKind, errors: &mut Vec<ParseError>) -> Option<i32> {
+        if value.is_none() {
+            errors.push(ParseError::new(kind, &self.span));
+        }
+        return value;
+    }
+}
+
+// symbol names follow the usual identifier rules, but may not look like a
+// register, otherwise `JUMP r1` would be ambiguous
+pub fn is_symbol_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    let starts_ok = match chars.next() {
+        Some(c) => c.is_ascii_alphabetic() || c == '_',
+        None => false,
+    };
+
+    return starts_ok
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !looks_like_register(s);
+}
+
+// Labels defined inside a macro, local ones included, are renamed to `name@N`
+// in each expansion. Users can't write names like that, so they can't clash
+// with anything.
+pub fn is_symbol_token(token: &Token) -> bool {
+    if token.kind != TokenKind::Word {
+        return false;
+    }
+    if token.span.expansion.is_some() {
+        if let Some((name, id)) = token.text.split_once('@') {
+            return (is_symbol_name(name) || is_local_label(name))
+                && !id.is_empty() && id.chars().all(|c| c.is_ascii_digit());
+        }
+    }
+    return is_symbol_name(&token.text);
+}
+
+// a symbol from another file's namespace, like `math::mul`
+fn is_qualified_name(s: &str) -> bool {
+    return s.split_once("::")
+        .is_some_and(|(namespace, name)| is_symbol_name(namespace) && is_symbol_name(name));
+}
+
+// `.name`, a label that belongs to the global label before it
+pub fn is_local_label(s: &str) -> bool {
+    return s.strip_prefix('.').is_some_and(is_symbol_name);
+}
+
+// numeric labels like `1:` can be defined any number of times, `1f` refers
+// to the next one and `1b` to the last one
+pub fn is_numeric_label(s: &str) -> bool {
+    return !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+}
+
+pub fn is_numeric_reference(s: &str) -> bool {
+    return s.strip_suffix(['f', 'b']).is_some_and(is_numeric_label);
+}
+
+// r or R followed by nothing or a digit. Anything like this is reserved for
+// registers, so `r` and `r2x` are bad registers rather than symbol names
+pub fn looks_like_register(s: &str) -> bool {
+    let mut chars = s.chars();
+    return matches!(chars.next(), Some('r') | Some('R'))
+        && chars.next().is_none_or(|c| c.is_ascii_digit());
+}
+
+/// Parses an expression starting at `tokens[*pos]`, leaving `pos` just after
+/// it. The expression ends at the first token that can't continue it.
+pub fn parse_expr(tokens: &[Token], pos: &mut usize, end_span: &Span) -> Result<Expr, ParseError> {
+    let mut lhs = parse_term(tokens, pos, end_span)?;
+    while let Some(op) = tokens.get(*pos).and_then(|t| match t.kind {
+        TokenKind::Plus => Some(BinaryOp::Add),
+        TokenKind::Minus => Some(BinaryOp::Sub),
+        _ => None,
+    }) {
+        *pos += 1;
+        let rhs = parse_term(tokens, pos, end_span)?;
+        let span = lhs.span.join(&rhs.span);
+        lhs = Expr { kind: ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)), span };
+    }
+    return Ok(lhs);
+}
+
+fn parse_term(tokens: &[Token], pos: &mut usize, end_span: &Span) -> Result<Expr, ParseError> {
+    let mut lhs = parse_unary(tokens, pos, end_span)?;
+    while let Some(op) = tokens.get(*pos).and_then(|t| match t.kind {
+        TokenKind::Star => Some(BinaryOp::Mul),
+        TokenKind::Slash => Some(BinaryOp::Div),
+        _ => None,
+    }) {
+        *pos += 1;
+        let rhs = parse_unary(tokens, pos, end_span)?;
+        let span = lhs.span.join(&rhs.span);
+        lhs = Expr { kind: ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)), span };
+    }
+    return Ok(lhs);
+}
+
+fn parse_unary(tokens: &[Token], pos: &mut usize, end_span: &Span) -> Result<Expr, ParseError> {
+    let minus = match tokens.get(*pos) {
+        Some(token) if token.kind == TokenKind::Minus => token,
+        _ => return parse_primary(tokens, pos, end_span),
+    };
+    *pos += 1;
+
+    // read -123 as a single literal, so that -2147483648 is allowed even
+    // though 2147483648 on its own doesn't fit
+    if let Some(token) = tokens.get(*pos) {
+        if token.kind == TokenKind::Word && is_number_literal(&token.text) {
+            *pos += 1;
+            let span = minus.span.join(&token.span);
+            return match literal::parse_number(&format!("-{}", token.text)) {
+                Ok(x) => Ok(Expr { kind: ExprKind::Number(x), span }),
+                Err(kind) => Err(ParseError::new(kind, &span)),
+            };
+        }
+    }
+
+    let inner = parse_unary(tokens, pos, end_span)?;
+    let span = minus.span.join(&inner.span);
+    return Ok(Expr { kind: ExprKind::Neg(Box::new(inner)), span });
+}
+
+fn is_number_literal(s: &str) -> bool {
+    return s.starts_with(|c: char| c.is_ascii_digit() || c == '\'');
+}
+
+fn parse_primary(tokens: &[Token], pos: &mut usize, end_span: &Span) -> Result<Expr, ParseError> {
+    let token = match tokens.get(*pos) {
+        Some(token) => token,
+        None => return Err(ParseError::new(ParseErrorKind::ExpectedExpression, end_span)),
+    };
+
+    match token.kind {
+        TokenKind::LParen => {
+            *pos += 1;
+            let inner = parse_expr(tokens, pos, end_span)?;
+            return match tokens.get(*pos) {
+                Some(close) if close.kind == TokenKind::RParen => {
+                    *pos += 1;
+                    Ok(Expr { kind: inner.kind, span: token.span.join(&close.span) })
+                },
+                _ => Err(ParseError::new(ParseErrorKind::UnclosedParen, &token.span)),
+            };
+        },
+        TokenKind::Word => {
+            *pos += 1;
+            if is_local_label(&token.text) || is_numeric_reference(&token.text) {
+                return Ok(Expr { kind: ExprKind::Symbol(token.text.clone()), span: token.span.clone() });
+            }
+            if is_number_literal(&token.text) {
+                return match literal::parse_number(&token.text) {
+                    Ok(x) => Ok(Expr { kind: ExprKind::Number(x), span: token.span.clone() }),
+                    Err(kind) => Err(ParseError::new(kind, &token.span)),
+                };
+            }
+            if is_symbol_token(token) || is_qualified_name(&token.text) {
+                return Ok(Expr { kind: ExprKind::Symbol(token.text.clone()), span: token.span.clone() });
+            }
+            let kind = if looks_like_register(&token.text) {
+                ParseErrorKind::ExpectedExpression
+            } else {
+                ParseErrorKind::MalformedNumber(token.text.clone())
+            };
+            return Err(ParseError::new(kind, &token.span));
+        },
+        _ => return Err(ParseError::new(ParseErrorKind::ExpectedExpression, &token.span)),
+    }
+}
@@ -0,0 +1,191 @@
+// Rewrites source into the canonical layout used by `aaaasm fmt`:
+//
+//     .equ SIZE 4
+//
+//     loop:
+//         I_ADD    -1       :) count down
+//         M2R_LOAD BUF, r1
+//         JUMP_NEG loop
+//
+// Labels go on their own line at the start, everything else is indented,
+// apart from directives that shape the program rather than adding to it.
+// Mnemonics are upper case and registers lower case. Within each run of
+// lines without a blank line between them, operands and comments are lined
+// up in columns. Only the layout changes, lines the parser would reject
+// are tidied the same way and left otherwise alone.
+
+use crate::interpreter::INSTRUCTIONS;
+use crate::parser::lexer::{Token, TokenKind};
+use crate::parser::syntax::SyntaxTree;
+use crate::parser::{expr, Operand};
+
+const INDENT: &str = "    ";
+
+// directives that stay at the start of the line
+const OUTER_DIRECTIVES: &[&str] = &[
+    ".macro", ".endm", ".if", ".ifdef", ".ifndef", ".else", ".endif",
+    ".include", ".text", ".data", ".equ", ".alias", ".entry", ".machine",
+];
+
+enum Line {
+    Blank,
+    Comment { text: String, indented: bool },
+    // `head` is a label or a mnemonic, `rest` anything after it
+    Code { head: String, rest: String, indented: bool, comment: Option<String> },
+}
+
+fn is_operator(kind: TokenKind) -> bool {
+    return matches!(kind, TokenKind::Plus | TokenKind::Minus | TokenKind::Star | TokenKind::Slash);
+}
+
+fn is_register(token: &Token) -> bool {
+    return token.kind == TokenKind::Word && expr::looks_like_register(&token.text)
+        && token.text.parse::<Operand>().is_ok();
+}
+
+// puts the tokens back together with consistent spacing. `named` is set when
+// the first token is a name, like the one `.equ` defines, rather than the
+// start of an expression
+fn join_tokens(tokens: &[Token], named: bool) -> String {
+    let mut out = String::new();
+    let mut prev: Option<&Token> = None;
+    let mut prev_unary = false;
+    for (idx, token) in tokens.iter().enumerate() {
+        let space = match prev {
+            None => false,
+            Some(_) if token.kind == TokenKind::Comma || token.kind == TokenKind::RParen => false,
+            Some(prev) => prev.kind != TokenKind::LParen && !prev_unary,
+        };
+        if space {
+            out.push(' ');
+        }
+
+        // a minus at the start of an operand is a sign, not a subtraction
+        let operand_start = match prev {
+            None => true,
+            Some(_) if named && idx == 1 => true,
+            Some(prev) => matches!(prev.kind, TokenKind::Comma | TokenKind::LParen) || is_operator(prev.kind)
+                || is_register(prev),
+        };
+        prev_unary = token.kind == TokenKind::Minus && operand_start;
+
+        if is_register(token) {
+            out += &token.text.to_ascii_lowercase();
+        } else {
+            out += &token.text;
+        }
+        prev = Some(token);
+    }
+    return out;
+}
+
+fn code_line(tokens: &[Token], comment: Option<String>, macros: &[String]) -> Line {
+    let head = &tokens[0];
+    let upper = head.text.to_ascii_uppercase();
+    let is_mnemonic = !macros.contains(&head.text) && INSTRUCTIONS.iter().any(|info| info.mnemonic == upper);
+    return Line::Code {
+        head: if is_mnemonic { upper } else { head.text.clone() },
+        rest: join_tokens(&tokens[1..], head.text == ".equ"),
+        indented: !OUTER_DIRECTIVES.contains(&head.text.as_str()),
+        comment,
+    };
+}
+
+// lays out one run of lines that has no blank lines in it
+fn format_block(block: &[Line], out: &mut String) {
+    let head_width = block.iter()
+        .filter_map(|line| match line {
+            Line::Code { head, indented: true, .. } => Some(head.len()),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0);
+
+    let code: Vec<Option<String>> = block.iter()
+        .map(|line| match line {
+            Line::Code { head, rest, indented, .. } if rest.is_empty() => {
+                Some(format!("{}{}", if *indented { INDENT } else { "" }, head))
+            },
+            Line::Code { head, rest, indented: true, .. } => {
+                Some(format!("{}{:width$} {}", INDENT, head, rest, width = head_width))
+            },
+            Line::Code { head, rest, indented: false, .. } => Some(format!("{} {}", head, rest)),
+            _ => None,
+        })
+        .collect();
+
+    let comment_column = code.iter().flatten().map(|text| text.len()).max().unwrap_or(0) + 2;
+
+    for (line, code) in block.iter().zip(code.iter()) {
+        match (line, code) {
+            (Line::Comment { text, indented }, _) => {
+                out.push_str(if *indented { INDENT } else { "" });
+                out.push_str(text);
+            },
+            (Line::Code { comment: Some(comment), .. }, Some(code)) => {
+                out.push_str(&format!("{:width$}{}", code, comment, width = comment_column));
+            },
+            (_, Some(code)) => out.push_str(code),
+            _ => {},
+        }
+        out.push('\n');
+    }
+}
+
+/// Formats a whole file. Every line of the result ends with a newline.
+pub fn format_source(s: &str) -> String {
+    let tree = SyntaxTree::parse("<input>", s);
+    let lexed: Vec<_> = tree.lines.iter().map(|line| line.lexed()).collect();
+
+    // a macro can share its name with an instruction in a different case, so
+    // calls to macros are left as they are
+    let macros: Vec<String> = lexed.iter()
+        .filter(|line| line.tokens.first().is_some_and(|token| token.text == ".macro"))
+        .filter_map(|line| line.tokens.get(1).map(|token| token.text.clone()))
+        .collect();
+
+    let mut lines = Vec::new();
+    for (line, syntax) in lexed.into_iter().zip(tree.lines.iter()) {
+        let comment = line.comment.map(|comment| comment.text.trim_end().to_string());
+        let mut tokens = &line.tokens[..];
+
+        if tokens.is_empty() {
+            lines.push(match comment {
+                Some(text) => Line::Comment { text, indented: syntax.tokens[0].kind == TokenKind::Whitespace },
+                None => Line::Blank,
+            });
+            continue;
+        }
+
+        if tokens.len() >= 2 && tokens[0].kind == TokenKind::Word && tokens[1].kind == TokenKind::Colon {
+            let label = format!("{}:", tokens[0].text);
+            tokens = &tokens[2..];
+            let label_comment = if tokens.is_empty() { comment.clone() } else { None };
+            lines.push(Line::Code { head: label, rest: String::new(), indented: false, comment: label_comment });
+            if tokens.is_empty() {
+                continue;
+            }
+        }
+
+        lines.push(code_line(tokens, comment, &macros));
+    }
+
+    // no blank lines at the end
+    while matches!(lines.last(), Some(Line::Blank)) {
+        lines.pop();
+    }
+
+    let mut out = String::new();
+    let mut block = Vec::new();
+    for line in lines.into_iter() {
+        if let Line::Blank = line {
+            format_block(&block, &mut out);
+            block.clear();
+            out.push('\n');
+        } else {
+            block.push(line);
+        }
+    }
+    format_block(&block, &mut out);
+    return out;
+}
@@ -0,0 +1,144 @@
+// `.include "path"` pulls the lines of another file in where the directive
+// is written. Paths are relative to the file doing the including. A file
+// that has already been included is skipped if it's included again, so two
+// libraries can both include a third, but a file that ends up including
+// itself is an error. Files are only read and lexed once per `IncludeCache`,
+// so a document that's parsed again after an edit doesn't read them again.
+//
+// Includes are followed while macros are expanded, so an `.include` in a
+// conditional block that isn't taken never reads its file.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+use crate::parser::lexer::{Token, TokenKind};
+use crate::parser::syntax::SyntaxTree;
+use crate::parser::{literal, unexpected, ParseError, ParseErrorKind, Span};
+
+/// Reads the contents of an included file.
+pub type FileLoader<'a> = dyn FnMut(&Path) -> std::io::Result<String> + 'a;
+
+// included files that have already been read, by path
+#[derive(Debug, Clone, Default)]
+pub struct IncludeCache {
+    trees: HashMap<PathBuf, SyntaxTree>,
+}
+
+// keeps track of the files being included while the program is expanded
+pub struct Includer<'a, 'l> {
+    load: &'a mut FileLoader<'l>,
+    cache: &'a mut IncludeCache,
+    // the files currently being read, innermost last
+    stack: Vec<PathBuf>,
+    seen: HashSet<PathBuf>,
+    // each file that was read, in the order they were first read, starting
+    // with the root file
+    pub files: Vec<Rc<str>>,
+}
+
+// tidies up `a/./b/../c` into `a/c` without touching the file system, so the
+// same file is recognised however it was reached
+fn normalise(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir if matches!(out.components().next_back(), Some(Component::Normal(_))) => {
+                out.pop();
+            },
+            _ => out.push(component),
+        }
+    }
+    return out;
+}
+
+impl<'a, 'l> Includer<'a, 'l> {
+    // `file_name` is the root file, includes are looked up relative to it.
+    // Files that aren't in `cache` are read with `load` and added to it
+    pub fn new(file_name: &str, load: &'a mut FileLoader<'l>, cache: &'a mut IncludeCache) -> Includer<'a, 'l> {
+        let path = normalise(Path::new(file_name));
+        return Includer {
+            load,
+            cache,
+            stack: vec![path.clone()],
+            seen: HashSet::from([path]),
+            files: vec![Rc::from(file_name)],
+        };
+    }
+
+    // reads the file named by an `.include` line, giving its name and lines.
+    // The file stays open until `close` is called, so that includes in it
+    // are relative to it. None if there's nothing to read, because of an
+    // error or because the file was already included
+    pub fn open(&mut self, tokens: &[Token], end_span: &Span,
+                errors: &mut Vec<ParseError>) -> Option<(Rc<str>, SyntaxTree)> {
+        let name = match tokens.get(1) {
+            Some(token) if token.kind == TokenKind::Word && token.text.starts_with('"') => token,
+            Some(token) => {
+                errors.push(ParseError::new(ParseErrorKind::Expected("a \"path\""), &token.span));
+                return None;
+            },
+            None => {
+                errors.push(ParseError::new(ParseErrorKind::Expected("a \"path\""), end_span));
+                return None;
+            },
+        };
+        if let Some(extra) = tokens.get(2) {
+            errors.push(unexpected(extra));
+            return None;
+        }
+
+        let relative: String = match literal::parse_string(&name.text) {
+            Ok(chars) => chars.into_iter().filter_map(|c| char::from_u32(c as u32)).collect(),
+            Err(kind) => {
+                errors.push(ParseError::new(kind, &name.span));
+                return None;
+            },
+        };
+
+        let including = self.stack.last().unwrap();
+        let path = normalise(&including.parent().unwrap_or(Path::new("")).join(relative));
+
+        if let Some(idx) = self.stack.iter().position(|p| *p == path) {
+            let chain: Vec<_> = self.stack[idx..].iter().chain([&path])
+                .map(|p| p.to_string_lossy().into_owned())
+                .collect();
+            errors.push(ParseError::new(ParseErrorKind::IncludeCycle(chain), &name.span));
+            return None;
+        }
+        if self.seen.contains(&path) {
+            return None;
+        }
+
+        let file: Rc<str> = Rc::from(path.to_string_lossy().as_ref());
+        let tree = match self.cache.trees.get(&path) {
+            Some(tree) => tree.clone(),
+            None => match (self.load)(&path) {
+                Ok(text) => {
+                    let tree = SyntaxTree::parse_file(&file, &text);
+                    self.cache.trees.insert(path.clone(), tree.clone());
+                    tree
+                },
+                Err(err) => {
+                    let kind = ParseErrorKind::IncludeFailed {
+                        path: path.to_string_lossy().into_owned(),
+                        reason: err.to_string(),
+                    };
+                    errors.push(ParseError::new(kind, &name.span));
+                    return None;
+                },
+            },
+        };
+
+        self.files.push(file.clone());
+        self.seen.insert(path.clone());
+        self.stack.push(path);
+        return Some((file, tree));
+    }
+
+    // finishes the file opened last
+    pub fn close(&mut self) {
+        self.stack.pop();
+    }
+}
@@ -0,0 +1,171 @@
+// Splits lines of AAAASM source into tokens. Any run of spaces
+// and tabs separates tokens, and comments (starting with :) or :( ) can
+// begin anywhere on a line and run until the end of it. Nothing is thrown
+// away here, the whitespace and comments are tokens too, so the tokens of
+// a line always add up to the line itself.
+
+use std::rc::Rc;
+
+use crate::parser::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Word, // mnemonics, labels, registers and numbers
+    Colon,
+    Comma,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    LParen,
+    RParen,
+    Comment(CommentKind), // includes the :) or :( marker
+    Whitespace, // a run of spaces and tabs
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommentKind {
+    Happy, // :)
+    Sad, // :(
+}
+
+impl TokenKind {
+    /// Whether the token has no meaning to the program, like whitespace
+    /// and comments.
+    pub fn is_trivia(&self) -> bool {
+        return matches!(self, TokenKind::Comment(_) | TokenKind::Whitespace);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    pub span: Span,
+}
+
+// the tokens of a line that mean something, and its comment if it has one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LexedLine {
+    pub tokens: Vec<Token>,
+    pub comment: Option<Token>,
+}
+
+// a line of source, along with enough context to point errors at it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLine {
+    pub file: Rc<str>,
+    pub number: usize,
+    pub text: Rc<str>,
+}
+
+impl SourceLine {
+    pub fn span(&self, start: usize, end: usize) -> Span {
+        return Span {
+            file: self.file.clone(),
+            line: self.number,
+            start,
+            end,
+            line_text: self.text.clone(),
+            expansion: None,
+        };
+    }
+}
+
+fn comment_kind(rest: &str) -> Option<CommentKind> {
+    if rest.starts_with(":)") {
+        return Some(CommentKind::Happy);
+    } else if rest.starts_with(":(") {
+        return Some(CommentKind::Sad);
+    }
+    return None;
+}
+
+fn is_separator(c: char) -> bool {
+    return c == ' ' || c == '\t';
+}
+
+fn punctuation(c: char) -> Option<TokenKind> {
+    return match c {
+        ':' => Some(TokenKind::Colon),
+        ',' => Some(TokenKind::Comma),
+        '+' => Some(TokenKind::Plus),
+        '-' => Some(TokenKind::Minus),
+        '*' => Some(TokenKind::Star),
+        '/' => Some(TokenKind::Slash),
+        '(' => Some(TokenKind::LParen),
+        ')' => Some(TokenKind::RParen),
+        _ => None,
+    };
+}
+
+// `::` between two names is part of the word, like `math::mul`
+fn is_path_separator(word: &str, rest: &str) -> bool {
+    return !word.is_empty()
+        && rest.starts_with("::")
+        && rest[2..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_');
+}
+
+// quoted characters like ' ' or "a, b" are kept inside the word
+fn word_len(rest: &str) -> usize {
+    let mut quote = None;
+    let mut escaped = false;
+    let mut in_separator = false;
+    for (idx, c) in rest.char_indices() {
+        if in_separator {
+            in_separator = false;
+        } else if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+        } else if c == '\'' || c == '"' {
+            quote = Some(c);
+        } else if is_path_separator(&rest[..idx], &rest[idx..]) {
+            in_separator = true;
+        } else if is_separator(c) || punctuation(c).is_some() {
+            return idx;
+        }
+    }
+    return rest.len();
+}
+
+/// Every token on the line, including whitespace and comments.
+pub fn tokenize_all(line: &SourceLine) -> Vec<Token> {
+    let text = &*line.text;
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while pos < text.len() {
+        let rest = &text[pos..];
+        let c = rest.chars().next().unwrap();
+
+        let (kind, len) = if is_separator(c) {
+            (TokenKind::Whitespace, rest.find(|c| !is_separator(c)).unwrap_or(rest.len()))
+        } else if let Some(comment) = comment_kind(rest) {
+            (TokenKind::Comment(comment), rest.len())
+        } else if let Some(kind) = punctuation(c) {
+            (kind, 1)
+        } else {
+            (TokenKind::Word, word_len(rest))
+        };
+
+        tokens.push(Token { kind, text: rest[..len].to_string(), span: line.span(pos, pos + len) });
+        pos += len;
+    }
+
+    return tokens;
+}
+
+pub fn tokenize(line: &SourceLine) -> LexedLine {
+    let mut tokens = tokenize_all(line);
+    tokens.retain(|token| token.kind != TokenKind::Whitespace);
+    let comment = match tokens.last() {
+        Some(token) if token.kind.is_trivia() => tokens.pop(),
+        _ => None,
+    };
+    return LexedLine { tokens, comment };
+}
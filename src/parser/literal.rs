@@ -0,0 +1,104 @@
+use crate::parser::ParseErrorKind;
+
+// Numeric literals can be written in decimal, hex (0x1F), binary (0b1010),
+// octal (0o17) or as a character ('A'), and may contain underscores between
+// digits (1_000). Decimal values must fit in an i32. Hex, binary and octal
+// are treated as bit patterns, so anything that fits in 32 bits is allowed
+// and 0xFFFFFFFF is -1.
+
+pub fn parse_number(s: &str) -> Result<i32, ParseErrorKind> {
+    if s.starts_with('\'') {
+        return parse_char(s);
+    }
+
+    let (negative, unsigned) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+
+    let (radix, digits) = match unsigned.get(..2) {
+        Some("0x") | Some("0X") => (16, &unsigned[2..]),
+        Some("0b") | Some("0B") => (2, &unsigned[2..]),
+        Some("0o") | Some("0O") => (8, &unsigned[2..]),
+        _ => (10, unsigned),
+    };
+
+    // underscores are only allowed between digits, not straight after a prefix
+    if digits.is_empty() || digits.starts_with('_') || digits.ends_with('_') {
+        return Err(ParseErrorKind::MalformedNumber(s.to_string()));
+    }
+
+    let mut magnitude: u64 = 0;
+    for c in digits.chars().filter(|c| *c != '_') {
+        let digit = match c.to_digit(radix) {
+            Some(d) => d as u64,
+            None => return Err(ParseErrorKind::MalformedNumber(s.to_string())),
+        };
+        magnitude = match magnitude.checked_mul(radix as u64).and_then(|m| m.checked_add(digit)) {
+            Some(m) => m,
+            None => return Err(ParseErrorKind::NumberOutOfRange(s.to_string())),
+        };
+    }
+
+    let limit = if negative {
+        -(i32::MIN as i64) as u64
+    } else if radix == 10 {
+        i32::MAX as u64
+    } else {
+        u32::MAX as u64
+    };
+
+    if magnitude > limit {
+        return Err(ParseErrorKind::NumberOutOfRange(s.to_string()));
+    }
+
+    if negative {
+        return Ok((-(magnitude as i64)) as i32);
+    }
+    return Ok(magnitude as u32 as i32);
+}
+
+// the text between a pair of matching quotes
+fn unquote(s: &str, quote: char) -> Option<&str> {
+    if s.len() >= 2 && s.starts_with(quote) && s.ends_with(quote) {
+        return Some(&s[1..s.len() - 1]);
+    }
+    return None;
+}
+
+// turns backslash escapes into the characters they stand for
+fn unescape(s: &str) -> Option<Vec<char>> {
+    let mut out = Vec::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        out.push(match c {
+            '\\' => match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                '0' => '\0',
+                '\\' => '\\',
+                '\'' => '\'',
+                '"' => '"',
+                _ => return None,
+            },
+            c => c,
+        });
+    }
+    return Some(out);
+}
+
+fn parse_char(s: &str) -> Result<i32, ParseErrorKind> {
+    return match unquote(s, '\'').and_then(unescape).as_deref() {
+        Some([c]) => Ok(*c as i32),
+        _ => Err(ParseErrorKind::MalformedNumber(s.to_string())),
+    };
+}
+
+/// Parses a "double quoted" string into one word per character.
+pub fn parse_string(s: &str) -> Result<Vec<i32>, ParseErrorKind> {
+    return match unquote(s, '"').and_then(unescape) {
+        Some(chars) => Ok(chars.into_iter().map(|c| c as i32).collect()),
+        None => Err(ParseErrorKind::MalformedString(s.to_string())),
+    };
+}
@@ -0,0 +1,123 @@
+// Local and numeric labels, which save inventing a new name for every loop:
+//
+//     count:
+//     .loop:  I_ADD -1        :) count.loop
+//             JUMP_NEG .loop
+//     1:      JUMP 1f         :) the next `1:`
+//     1:      JUMP 1b         :) the last `1:`, so this one
+//
+// A local label belongs to the global label before it, so `.loop` can be
+// used again under another label. Numeric labels can be defined any number
+// of times. Both are given unique names here, before anything looks up
+// symbols, so the rest of the parser only ever sees ordinary labels.
+
+use std::collections::HashMap;
+
+use crate::parser::{expr, DataDirective, OperandExpr, Statement, StatementBody, SymbolTable};
+use crate::parser::{Expr, ParseError, ParseErrorKind, Span};
+
+fn exprs_mut(body: &mut StatementBody) -> Vec<&mut Expr> {
+    return match body {
+        StatementBody::Instruction { operands, .. } => operands.iter_mut()
+            .filter_map(|op| match op {
+                OperandExpr::Number(expr) => Some(expr),
+                OperandExpr::Register(_, _) => None,
+            })
+            .collect(),
+        StatementBody::Constant { value, .. } => vec![value],
+        StatementBody::Machine { registers, memory, .. } => vec![registers, memory],
+        StatementBody::Org(address) | StatementBody::Entry(address) => vec![address],
+        StatementBody::Data { data: DataDirective::Words(values), .. } => values.iter_mut().collect(),
+        StatementBody::Data { data: DataDirective::Fill { count, value }, .. } => vec![count, value],
+        StatementBody::Data { data: DataDirective::Str(_), .. }
+            | StatementBody::Alias { .. }
+            | StatementBody::Section(_) => vec![],
+    };
+}
+
+// the global label that local labels currently belong to, and the file it's
+// in, since a new file starts without one
+struct Scope {
+    label: Option<String>,
+    file: Option<std::rc::Rc<str>>,
+}
+
+impl Scope {
+    fn qualify(&self, name: &str, span: &Span) -> Result<String, ParseError> {
+        return match &self.label {
+            Some(label) if self.file.as_ref() == Some(&span.root().file) => Ok(format!("{}{}", label, name)),
+            _ => Err(ParseError::new(ParseErrorKind::NoGlobalLabel(name.to_string()), span)),
+        };
+    }
+}
+
+/// Renames local and numeric labels, and references to them, to names that
+/// are unique to the program. References that can't be resolved are
+/// reported, and marked in `symbols` so they aren't reported again.
+pub fn resolve_local_labels(statements: &mut [Statement], symbols: &mut SymbolTable,
+                            errors: &mut Vec<ParseError>) {
+    // where each numeric label is defined, in order
+    let mut numeric: HashMap<String, Vec<usize>> = HashMap::new();
+    for (idx, statement) in statements.iter().enumerate() {
+        if let Some(label) = statement.label.as_ref().filter(|label| expr::is_numeric_label(&label.name)) {
+            numeric.entry(label.name.clone()).or_default().push(idx);
+        }
+    }
+
+    let mut scope = Scope { label: None, file: None };
+    for (idx, statement) in statements.iter_mut().enumerate() {
+        if let Some(label) = &mut statement.label {
+            // labels from macros don't start a scope, so a macro can be used
+            // in the middle of a loop. The local labels a macro defines have
+            // already been given names of their own for each expansion
+            if expr::is_symbol_name(&label.name) && label.span.expansion.is_none() {
+                scope = Scope { label: Some(label.name.clone()), file: Some(label.span.root().file.clone()) };
+            } else if expr::is_local_label(&label.name) {
+                match scope.qualify(&label.name, &label.span) {
+                    Ok(name) => label.name = name,
+                    Err(err) => errors.push(err),
+                }
+            } else if expr::is_numeric_label(&label.name) {
+                label.name = format!("{}:{}", label.name, idx);
+            }
+        }
+
+        let body = match &mut statement.body {
+            Some(body) => body,
+            None => continue,
+        };
+
+        for expr in exprs_mut(body) {
+            expr.symbols_mut(&mut |name, span| {
+                let resolved = if expr::is_local_label(name) {
+                    scope.qualify(name, span)
+                } else if expr::is_numeric_reference(name) {
+                    let (number, direction) = name.split_at(name.len() - 1);
+                    let defs = numeric.get(number).map_or(&[][..], |defs| &defs[..]);
+                    // a backward reference can be to a label on the same line
+                    let def = if direction == "f" {
+                        defs.iter().find(|def| **def > idx)
+                    } else {
+                        defs.iter().rev().find(|def| **def <= idx)
+                    };
+                    match def {
+                        Some(def) => Ok(format!("{}:{}", number, def)),
+                        None => Err(ParseError::new(ParseErrorKind::MissingNumericLabel(name.clone()), span)),
+                    }
+                } else {
+                    return;
+                };
+
+                match resolved {
+                    Ok(resolved) => *name = resolved,
+                    Err(err) => {
+                        // give it a name of its own that's already known to be bad
+                        *name = format!("{}@{}:{}", name, span.line, span.start);
+                        symbols.values.insert(name.clone(), None);
+                        errors.push(err);
+                    },
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,408 @@
+// Macros let a sequence of lines be written once and used many times:
+//
+//     .macro copy from, to
+//         R2A_LOAD from
+//         A2R_STORE to
+//     .endm
+//
+//     copy r1, r2
+//
+// Arguments are separated by commas and replace each use of the matching
+// parameter in the body. Labels defined in the body, `.name` labels too, are
+// local to each expansion, so a macro with a loop in it can be used more than
+// once.
+// Macros must be defined before they are used, and are expanded before
+// anything else looks at the statements. Included files are read here too,
+// as each `.include` is reached.
+//
+// Conditional blocks (`.if`, `.ifdef`, `.ifndef`, `.else`, `.endif`) are
+// dealt with here too, so that a condition can decide whether a macro gets
+// defined or used. A block inside a macro body is decided each time the
+// macro is used, and must be closed before the end of the body.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::parser::lexer::{Token, TokenKind};
+use crate::parser::includes::Includer;
+use crate::parser::syntax::SyntaxLine;
+use crate::parser::{expr, parse_name, parse_statement, span_after, unexpected};
+use crate::parser::{Expansion, Ident, ParseError, ParseErrorKind, Span, Statement, StatementBody};
+use crate::parser::{SymbolTable, SymbolValue};
+
+// deep enough for any sensible nesting, shallow enough to stop a macro that
+// uses itself before it overflows the stack
+const MAX_DEPTH: usize = 64;
+
+struct Macro {
+    name: Ident,
+    params: Vec<String>,
+    // the lexed lines of the body, each with the span just past its end
+    body: Vec<(Vec<Token>, Span)>,
+    // labels defined in the body
+    locals: Vec<String>,
+}
+
+// an .if, .ifdef or .ifndef that hasn't reached its .endif yet
+struct Conditional {
+    directive: Token,
+    // whether the lines around the block are being assembled
+    outer_active: bool,
+    // whether the condition was true
+    taken: bool,
+    else_token: Option<Token>,
+}
+
+impl Conditional {
+    fn active(&self) -> bool {
+        return self.outer_active && (self.taken == self.else_token.is_none());
+    }
+}
+
+struct Expander<'a, 'i, 'l> {
+    macros: HashMap<String, Rc<Macro>>,
+    expansion_count: usize,
+    statements: Vec<Statement>,
+    // constants defined so far, for conditions
+    symbols: SymbolTable,
+    conditionals: Vec<Conditional>,
+    // conditionals below this were opened outside the current macro body,
+    // so it can't close them
+    conditional_floor: usize,
+    includer: &'a mut Includer<'i, 'l>,
+    errors: &'a mut Vec<ParseError>,
+}
+
+fn is_directive(tokens: &[Token], name: &str) -> bool {
+    return tokens.first().is_some_and(|token| token.kind == TokenKind::Word && token.text == name);
+}
+
+// splits a comma separated list, commas inside brackets don't count
+fn split_args(tokens: &[Token], end_span: &Span) -> Result<Vec<Vec<Token>>, ParseError> {
+    let mut args = Vec::new();
+    if tokens.is_empty() {
+        return Ok(args);
+    }
+
+    let mut current = Vec::new();
+    let mut depth = 0;
+    for token in tokens.iter() {
+        match token.kind {
+            TokenKind::LParen => depth += 1,
+            TokenKind::RParen => depth -= 1,
+            TokenKind::Comma if depth == 0 => {
+                if current.is_empty() {
+                    return Err(ParseError::new(ParseErrorKind::Expected("an argument"), &token.span));
+                }
+                args.push(std::mem::take(&mut current));
+                continue;
+            },
+            _ => {},
+        }
+        current.push(token.clone());
+    }
+
+    if current.is_empty() {
+        return Err(ParseError::new(ParseErrorKind::Expected("an argument"), end_span));
+    }
+    args.push(current);
+    return Ok(args);
+}
+
+impl Expander<'_, '_, '_> {
+    fn active(&self) -> bool {
+        return self.conditionals.last().is_none_or(Conditional::active);
+    }
+
+    // whether the condition of an .if, .ifdef or .ifndef holds
+    fn condition(&mut self, tokens: &[Token], end_span: &Span) -> Result<bool, ParseError> {
+        let args = &tokens[1..];
+        if tokens[0].text == ".if" {
+            let mut pos = 0;
+            let condition = expr::parse_expr(args, &mut pos, end_span)?;
+            if let Some(extra) = args.get(pos) {
+                return Err(unexpected(extra));
+            }
+            return Ok(self.symbols.eval(&condition, self.errors).is_some_and(|value| value != 0));
+        }
+
+        let name = parse_name(args.first(), end_span)?;
+        if let Some(extra) = args.get(1) {
+            return Err(unexpected(extra));
+        }
+        let defined = self.symbols.is_defined(&name.name, &name.span);
+        return Ok(defined == (tokens[0].text == ".ifdef"));
+    }
+
+    // deals with conditional directives and lines inside blocks that aren't
+    // being assembled, returning false for any other line
+    fn skip_line(&mut self, tokens: &[Token], end_span: &Span) -> bool {
+        let directive = match tokens.first() {
+            Some(token) if token.kind == TokenKind::Word => token,
+            _ => return !self.active(),
+        };
+
+        match directive.text.as_str() {
+            ".if" | ".ifdef" | ".ifndef" => {
+                let outer_active = self.active();
+                // conditions in blocks that are being skipped aren't looked at
+                let taken = outer_active && match self.condition(tokens, end_span) {
+                    Ok(taken) => taken,
+                    Err(err) => {
+                        self.errors.push(err);
+                        false
+                    },
+                };
+                self.conditionals.push(Conditional { directive: directive.clone(), outer_active, taken,
+                                                     else_token: None });
+            },
+            ".else" | ".endif" if self.conditionals.len() <= self.conditional_floor => {
+                self.errors.push(ParseError::new(ParseErrorKind::Unmatched(directive.text.clone()),
+                                                 &directive.span));
+            },
+            ".else" => {
+                if let Some(extra) = tokens.get(1) {
+                    self.errors.push(unexpected(extra));
+                }
+                let conditional = self.conditionals.last_mut().unwrap();
+                if conditional.else_token.is_some() {
+                    self.errors.push(unexpected(directive));
+                }
+                conditional.else_token = Some(directive.clone());
+            },
+            ".endif" => {
+                if let Some(extra) = tokens.get(1) {
+                    self.errors.push(unexpected(extra));
+                }
+                self.conditionals.pop();
+            },
+            _ => return !self.active(),
+        }
+        return true;
+    }
+
+    // reports blocks opened above the floor that were never closed
+    fn close_conditionals(&mut self) {
+        while self.conditionals.len() > self.conditional_floor {
+            let conditional = self.conditionals.pop().unwrap();
+            let kind = ParseErrorKind::Unterminated {
+                directive: conditional.directive.text.clone(),
+                terminator: ".endif",
+            };
+            self.errors.push(ParseError::new(kind, &conditional.directive.span));
+        }
+    }
+
+    fn push_statement(&mut self, statement: Result<Statement, ParseError>) {
+        match statement {
+            Ok(statement) => {
+                // remember constants for later conditions, any problems with
+                // them are reported once everything has been expanded
+                if let Some(StatementBody::Constant { name, value }) = &statement.body {
+                    let _ = self.symbols.define(name, SymbolValue::Constant(value.clone()));
+                }
+                self.statements.push(statement);
+            },
+            Err(err) => self.errors.push(err),
+        }
+    }
+
+    // reads a definition, `lines` is left just after its .endm
+    fn define<'l>(&mut self, tokens: &[Token], end_span: &Span,
+                  lines: &mut impl Iterator<Item = &'l Rc<SyntaxLine>>) {
+        let mut body = Vec::new();
+        let mut closed = false;
+        for line in lines.by_ref() {
+            let line_tokens = line.lexed().tokens;
+            if is_directive(&line_tokens, ".endm") {
+                if let Some(extra) = line_tokens.get(1) {
+                    self.errors.push(unexpected(extra));
+                }
+                closed = true;
+                break;
+            }
+            if is_directive(&line_tokens, ".macro") {
+                self.errors.push(unexpected(&line_tokens[0]));
+                continue;
+            }
+            let line_end = span_after(&line.source, &line_tokens);
+            body.push((line_tokens, line_end));
+        }
+
+        if !closed {
+            let kind = ParseErrorKind::Unterminated { directive: ".macro".to_string(), terminator: ".endm" };
+            self.errors.push(ParseError::new(kind, &tokens[0].span));
+            return;
+        }
+
+        let name = match parse_name(tokens.get(1), end_span) {
+            Ok(name) => name,
+            Err(err) => return self.errors.push(err),
+        };
+
+        let mut params = Vec::new();
+        let param_tokens = match split_args(&tokens[2..], end_span) {
+            Ok(param_tokens) => param_tokens,
+            Err(err) => return self.errors.push(err),
+        };
+        for param in param_tokens.iter() {
+            if param.len() != 1 || !expr::is_symbol_name(&param[0].text) {
+                let kind = ParseErrorKind::InvalidLabel(param[0].text.clone());
+                return self.errors.push(ParseError::new(kind, &param[0].span));
+            }
+            params.push(param[0].text.clone());
+        }
+
+        let locals = body.iter()
+            // numeric labels already work in every expansion, while a `.name`
+            // label would belong to the same global label each time
+            .filter(|(line, _)| line.len() >= 2 && line[0].kind == TokenKind::Word
+                    && line[1].kind == TokenKind::Colon
+                    && (expr::is_symbol_name(&line[0].text) || expr::is_local_label(&line[0].text)))
+            .map(|(line, _)| line[0].text.clone())
+            .collect();
+
+        if let Some(existing) = self.macros.get(&name.name) {
+            let kind = ParseErrorKind::DuplicateSymbol {
+                name: name.name.clone(),
+                first_line: existing.name.span.line,
+            };
+            return self.errors.push(ParseError::new(kind, &name.span));
+        }
+
+        self.macros.insert(name.name.clone(), Rc::new(Macro { name, params, body, locals }));
+    }
+
+    // expands the lines of the file that was included by `tokens`
+    fn include(&mut self, tokens: &[Token], end_span: &Span) {
+        if let Some((file, tree)) = self.includer.open(tokens, end_span, self.errors) {
+            self.symbols.add_namespace(&file);
+            self.expand_file(&tree.lines);
+            self.includer.close();
+        }
+    }
+
+    fn expand_file(&mut self, lines: &[Rc<SyntaxLine>]) {
+        let mut lines = lines.iter();
+        while let Some(line) = lines.next() {
+            let tokens = line.lexed().tokens;
+            let end_span = span_after(&line.source, &tokens);
+            if self.skip_line(&tokens, &end_span) {
+                continue;
+            }
+            if is_directive(&tokens, ".macro") {
+                self.define(&tokens, &end_span, &mut lines);
+            } else if is_directive(&tokens, ".endm") {
+                self.errors.push(ParseError::new(ParseErrorKind::Unmatched(".endm".to_string()),
+                                                 &tokens[0].span));
+            } else {
+                self.process(tokens, &end_span, 0, Some(line));
+            }
+        }
+    }
+
+    // turns a line into statements, expanding it first if it uses a macro.
+    // `line` is where the tokens came from if they weren't made by a macro
+    fn process(&mut self, tokens: Vec<Token>, end_span: &Span, depth: usize, line: Option<&SyntaxLine>) {
+        if is_directive(&tokens, ".include") {
+            return self.include(&tokens, end_span);
+        }
+
+        let body_start = if tokens.len() >= 2 && tokens[1].kind == TokenKind::Colon { 2 } else { 0 };
+        let mac = match tokens.get(body_start) {
+            Some(token) if token.kind == TokenKind::Word => self.macros.get(&token.text).cloned(),
+            _ => None,
+        };
+
+        let mac = match mac {
+            Some(mac) => mac,
+            None => {
+                let statement = match line {
+                    Some(line) => line.statement(&tokens, end_span),
+                    None => parse_statement(&tokens, end_span),
+                };
+                return self.push_statement(statement);
+            },
+        };
+
+        // a label in front of a macro points at the start of its expansion
+        if body_start == 2 {
+            self.push_statement(parse_statement(&tokens[..2], end_span));
+        }
+
+        let call = &tokens[body_start];
+        let args = match split_args(&tokens[body_start + 1..], end_span) {
+            Ok(args) => args,
+            Err(err) => return self.errors.push(err),
+        };
+
+        if args.len() != mac.params.len() {
+            let kind = ParseErrorKind::WrongOperandCount {
+                mnemonic: mac.name.name.clone(),
+                expected: mac.params.len(),
+                found: args.len(),
+            };
+            return self.errors.push(ParseError::new(kind, &call.span));
+        }
+
+        if depth >= MAX_DEPTH {
+            let kind = ParseErrorKind::MacroRecursion(mac.name.name.clone());
+            return self.errors.push(ParseError::new(kind, &call.span));
+        }
+
+        self.expansion_count += 1;
+        let id = self.expansion_count;
+        let expansion = Rc::new(Expansion { macro_name: mac.name.name.clone(), call_site: call.span.clone() });
+        let outer_floor = std::mem::replace(&mut self.conditional_floor, self.conditionals.len());
+
+        for (line, line_end) in mac.body.iter() {
+            let mut expanded = Vec::new();
+            for token in line.iter() {
+                if token.kind == TokenKind::Word {
+                    if let Some(idx) = mac.params.iter().position(|param| *param == token.text) {
+                        expanded.extend(args[idx].iter().cloned());
+                        continue;
+                    }
+                }
+
+                let mut token = token.clone();
+                token.span.expansion = Some(expansion.clone());
+                if token.kind == TokenKind::Word && mac.locals.contains(&token.text) {
+                    token.text = format!("{}@{}", token.text, id);
+                }
+                expanded.push(token);
+            }
+
+            let mut end = line_end.clone();
+            end.expansion = Some(expansion.clone());
+            if !self.skip_line(&expanded, &end) {
+                self.process(expanded, &end, depth + 1, None);
+            }
+        }
+
+        self.close_conditionals();
+        self.conditional_floor = outer_floor;
+    }
+}
+
+/// Turns lines of source into statements, expanding any macros and includes
+/// on the way. Conditions are evaluated with `symbols`, which should already
+/// hold anything defined outside the source.
+pub fn expand_lines(lines: &[Rc<SyntaxLine>], symbols: SymbolTable, includer: &mut Includer,
+                    errors: &mut Vec<ParseError>) -> Vec<Statement> {
+    let mut expander = Expander {
+        macros: HashMap::new(),
+        expansion_count: 0,
+        statements: Vec::new(),
+        symbols,
+        conditionals: Vec::new(),
+        conditional_floor: 0,
+        includer,
+        errors,
+    };
+
+    expander.expand_file(lines);
+    expander.close_conditionals();
+
+    return expander.statements;
+}
@@ -1,6 +1,33 @@
-use crate::interpreter::Instruction;
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::interpreter::{DataBlock, Instruction, MachineConfig, OperandKind, OverflowMode, Program, SourceLocation};
+use crate::interpreter::{INSTRUCTIONS, MAX_MEMORY, MAX_REGISTERS, REG_NUMBER};
+use expr::{Expr, ExprKind};
+use includes::{IncludeCache, Includer};
+use lexer::SourceLine;
+mod error;
+mod disassemble;
+mod document;
+mod expr;
+mod format;
+mod includes;
+mod lexer;
+mod literal;
+mod locals;
+mod macros;
+mod syntax;
 mod tests;
 
+pub use error::{Expansion, ParseError, ParseErrorKind, Span};
+pub use disassemble::disassemble;
+pub use document::Document;
+pub use format::format_source;
+pub use includes::FileLoader;
+pub use lexer::{CommentKind, Token, TokenKind};
+pub use syntax::{SyntaxLine, SyntaxTree};
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 enum Operand {
     Register(i32), // like R1
@@ -8,8 +35,15 @@ enum Operand {
 }
 
 impl Operand {
-    fn type_matches(&self, other: &Operand) -> bool {
-        return std::mem::discriminant(self) == std::mem::discriminant(other);
+    fn kind_name(&self) -> &'static str {
+        return match self {
+            Operand::Register(_) => "register",
+            Operand::Number(_) => "number",
+        };
+    }
+
+    fn fits(&self, kind: OperandKind) -> bool {
+        return matches!(self, Operand::Register(_)) == (kind == OperandKind::Register);
     }
 
     fn inner(&self) -> i32 {
@@ -21,158 +55,1049 @@ impl Operand {
 }
 
 impl std::str::FromStr for Operand {
-    type Err = String;
+    type Err = ParseErrorKind;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let first = s.chars().nth(0).unwrap();
-        if first == 'R' || first == 'r' {
-            // register
-            if s.len() == 1 {
-                return Err("Attempted to parse register, but no register \
-                           number was given!".to_string())
-            }
-            return match (&s[1..]).parse::<i32>() {
+        if s.starts_with('R') || s.starts_with('r') {
+            // register, the number must follow straight after the r
+            return match s[1..].parse::<i32>() {
                 Ok(num) => Ok(Operand::Register(num)),
-                Err(err) => Err(format!("Attempted to parse {} as register number but failed! \
-                                        Error given: {}", &s[1..], err.to_string())),
+                Err(_) => Err(ParseErrorKind::MalformedRegister(s.to_string())),
             };
         } else {
             // other number
-            let number = s.parse::<i32>();
-            if number.is_err() {
-                return Err(number.unwrap_err().to_string());
-            }
-            return Ok(Operand::Number(number.unwrap()));
+            return Ok(Operand::Number(literal::parse_number(s)?));
         }
     }
 }
 
-fn matching_operand_formats(x: &Vec<Operand>, y: &Vec<Operand>) -> bool {
-    if x.len() != y.len() {
-        return false;
+// an operand as written in the source, numbers may still refer to symbols
+#[derive(Debug, Clone)]
+enum OperandExpr {
+    Register(i32, Span),
+    Number(Expr),
+}
+
+impl OperandExpr {
+    fn span(&self) -> &Span {
+        return match self {
+            OperandExpr::Register(_, span) => span,
+            OperandExpr::Number(expr) => &expr.span,
+        };
     }
+}
+
+// a name along with where it was written
+#[derive(Debug, Clone)]
+struct Ident {
+    name: String,
+    span: Span,
+}
 
-    return x.iter().zip(y.iter()).all(|(op1, op2)| op1.type_matches(op2));
+impl Ident {
+    fn from_token(token: &Token) -> Ident {
+        return Ident { name: token.text.clone(), span: token.span.clone() };
+    }
 }
 
-fn parse_operands(operands: &Vec<&str>) -> Result<Vec<Operand>, String> {
-    let ops:Vec<_> = operands.iter().map(|word| word.parse::<Operand>()).collect();
-    for op in ops.iter() {
-        if op.is_err() {
-            return Err(op.clone().unwrap_err());
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Text,
+    Data,
+}
+
+#[derive(Debug, Clone)]
+enum StatementBody {
+    Instruction { mnemonic: Ident, operands: Vec<OperandExpr> },
+    // .equ NAME value
+    Constant { name: Ident, value: Expr },
+    // .alias NAME rN
+    Alias { name: Ident, register: i32, register_span: Span },
+    // .text and .data
+    Section(Section),
+    // .org ADDR
+    Org(Expr),
+    // .entry TARGET
+    Entry(Expr),
+    // .machine REGISTERS, MEMORY[, OVERFLOW]
+    Machine { registers: Expr, memory: Expr, overflow: Option<OverflowMode> },
+    Data { directive: Ident, data: DataDirective },
+}
+
+#[derive(Debug, Clone)]
+enum DataDirective {
+    // .word v1, v2, ...
+    Words(Vec<Expr>),
+    // .fill N, v
+    Fill { count: Expr, value: Expr },
+    // .string "text"
+    Str(Vec<i32>),
+}
+
+// the label and body on one line, either of which may be missing
+#[derive(Debug, Clone)]
+struct Statement {
+    label: Option<Ident>,
+    body: Option<StatementBody>,
+}
+
+impl Statement {
+    // moves every span to `line`, for when lines above the one the statement
+    // was parsed from are added or removed
+    fn set_line(&mut self, line: usize) {
+        let mut set = |span: &mut Span| span.line = line;
+        if let Some(label) = &mut self.label {
+            set(&mut label.span);
+        }
+        let body = match &mut self.body {
+            Some(body) => body,
+            None => return,
+        };
+
+        match body {
+            StatementBody::Instruction { mnemonic, operands } => {
+                set(&mut mnemonic.span);
+                for operand in operands.iter_mut() {
+                    match operand {
+                        OperandExpr::Register(_, span) => set(span),
+                        OperandExpr::Number(expr) => expr.spans_mut(&mut set),
+                    }
+                }
+            },
+            StatementBody::Constant { name, value } => {
+                set(&mut name.span);
+                value.spans_mut(&mut set);
+            },
+            StatementBody::Alias { name, register_span, .. } => {
+                set(&mut name.span);
+                set(register_span);
+            },
+            StatementBody::Section(_) => {},
+            StatementBody::Org(expr) | StatementBody::Entry(expr) => expr.spans_mut(&mut set),
+            StatementBody::Machine { registers, memory, .. } => {
+                registers.spans_mut(&mut set);
+                memory.spans_mut(&mut set);
+            },
+            StatementBody::Data { directive, data } => {
+                set(&mut directive.span);
+                match data {
+                    DataDirective::Words(values) => {
+                        for value in values.iter_mut() {
+                            value.spans_mut(&mut set);
+                        }
+                    },
+                    DataDirective::Fill { count, value } => {
+                        count.spans_mut(&mut set);
+                        value.spans_mut(&mut set);
+                    },
+                    DataDirective::Str(_) => {},
+                }
+            },
         }
     }
-    return Ok(ops.into_iter().map(|op| op.unwrap()).collect());
 }
 
-pub fn parse_instruction(s: &str) -> Result<Instruction, String> {
-    let words:Vec<_> = s.split(' ').collect();
+fn unexpected(token: &Token) -> ParseError {
+    return ParseError::new(ParseErrorKind::UnexpectedToken(token.text.clone()), &token.span);
+}
+
+// the span just past the end of the last token, for pointing at missing things
+fn span_after(line: &SourceLine, tokens: &[Token]) -> Span {
+    return match tokens.last() {
+        Some(token) => {
+            let mut span = token.span.clone();
+            span.start = span.end;
+            span.end += 1;
+            span
+        },
+        None => line.span(0, 1),
+    };
+}
+
+fn parse_operand_list(tokens: &[Token], end_span: &Span) -> Result<Vec<OperandExpr>, ParseError> {
+    let mut operands = Vec::new();
+    let mut pos = 0;
 
-    if words.is_empty() {
-        panic!("parse instruction was passed a blank line.");
+    while pos < tokens.len() {
+        let token = &tokens[pos];
+        if token.kind == TokenKind::Word && expr::looks_like_register(&token.text) {
+            match token.text.parse::<Operand>() {
+                Ok(op) => operands.push(OperandExpr::Register(op.inner(), token.span.clone())),
+                Err(kind) => return Err(ParseError::new(kind, &token.span)),
+            }
+            pos += 1;
+        } else {
+            operands.push(OperandExpr::Number(expr::parse_expr(tokens, &mut pos, end_span)?));
+        }
+
+        // operands may optionally be separated by commas
+        match tokens.get(pos) {
+            Some(token) if token.kind == TokenKind::Comma && pos + 1 < tokens.len() => pos += 1,
+            Some(token) if token.kind == TokenKind::Comma || token.kind == TokenKind::RParen => {
+                return Err(unexpected(token));
+            },
+            _ => {},
+        }
     }
 
-    let ops = parse_operands(&words[1..].to_vec());
+    return Ok(operands);
+}
 
-    if ops.is_err()
-    {
-        return Err(ops.unwrap_err());
+// a comma separated list of expressions, with nothing after it
+fn parse_expr_list(tokens: &[Token], end_span: &Span) -> Result<Vec<Expr>, ParseError> {
+    let mut exprs = Vec::new();
+    let mut pos = 0;
+    loop {
+        exprs.push(expr::parse_expr(tokens, &mut pos, end_span)?);
+        match tokens.get(pos) {
+            Some(token) if token.kind == TokenKind::Comma => pos += 1,
+            Some(token) => return Err(unexpected(token)),
+            None => return Ok(exprs),
+        }
     }
+}
 
-    let ops = ops.unwrap();
+fn expect_no_args(args: &[Token]) -> Result<(), ParseError> {
+    return match args.first() {
+        Some(token) => Err(unexpected(token)),
+        None => Ok(()),
+    };
+}
 
+fn parse_name(token: Option<&Token>, end_span: &Span) -> Result<Ident, ParseError> {
+    return match token {
+        Some(token) if expr::is_symbol_token(token) => {
+            Ok(Ident::from_token(token))
+        },
+        Some(token) => Err(ParseError::new(ParseErrorKind::InvalidLabel(token.text.clone()), &token.span)),
+        None => Err(ParseError::new(ParseErrorKind::Expected("a name"), end_span)),
+    };
+}
 
-    let mut instruction = Instruction::NOOP();
+fn parse_directive(name: &Token, args: &[Token], end_span: &Span) -> Result<StatementBody, ParseError> {
+    let data = |data| Ok(StatementBody::Data { directive: Ident::from_token(name), data });
+    match name.text.as_str() {
+        ".equ" => {
+            let constant = parse_name(args.first(), end_span)?;
+            let mut pos = 1;
+            let value = expr::parse_expr(args, &mut pos, end_span)?;
+            if let Some(token) = args.get(pos) {
+                return Err(unexpected(token));
+            }
+            return Ok(StatementBody::Constant { name: constant, value });
+        },
+        ".alias" => {
+            let alias = parse_name(args.first(), end_span)?;
+            let register = match args.get(1) {
+                Some(token) if token.kind == TokenKind::Word && expr::looks_like_register(&token.text) => token,
+                Some(token) => return Err(ParseError::new(ParseErrorKind::Expected("a register"), &token.span)),
+                None => return Err(ParseError::new(ParseErrorKind::Expected("a register"), end_span)),
+            };
+            expect_no_args(&args[2..])?;
+            return match register.text.parse::<Operand>() {
+                Ok(op) => Ok(StatementBody::Alias { name: alias, register: op.inner(), register_span: register.span.clone() }),
+                Err(kind) => Err(ParseError::new(kind, &register.span)),
+            };
+        },
+        ".text" => {
+            expect_no_args(args)?;
+            return Ok(StatementBody::Section(Section::Text));
+        },
+        ".data" => {
+            expect_no_args(args)?;
+            return Ok(StatementBody::Section(Section::Data));
+        },
+        ".org" => {
+            let mut exprs = parse_expr_list(args, end_span)?;
+            if exprs.len() != 1 {
+                return Err(ParseError::new(ParseErrorKind::Expected("a single address"), &exprs[1].span));
+            }
+            return Ok(StatementBody::Org(exprs.remove(0)));
+        },
+        ".entry" => {
+            let mut exprs = parse_expr_list(args, end_span)?;
+            if exprs.len() != 1 {
+                return Err(ParseError::new(ParseErrorKind::Expected("a single target"), &exprs[1].span));
+            }
+            return Ok(StatementBody::Entry(exprs.remove(0)));
+        },
+        ".machine" => {
+            let mut exprs = parse_expr_list(args, end_span)?;
+            if exprs.len() < 2 || exprs.len() > 3 {
+                let span = exprs.get(3).map_or(end_span, |extra| &extra.span);
+                return Err(ParseError::new(ParseErrorKind::Expected("a register count and a memory size"), span));
+            }
+            // the overflow mode is a plain word, not a symbol
+            let mut overflow = None;
+            if let Some(expr) = exprs.get(2) {
+                overflow = match &expr.kind {
+                    ExprKind::Symbol(name) => name.parse().ok(),
+                    _ => None,
+                };
+                if overflow.is_none() {
+                    let kind = ParseErrorKind::Expected("wrapping, saturating or trapping");
+                    return Err(ParseError::new(kind, &expr.span));
+                }
+            }
+            let memory = exprs.remove(1);
+            return Ok(StatementBody::Machine { registers: exprs.remove(0), memory, overflow });
+        },
+        ".word" => {
+            return data(DataDirective::Words(parse_expr_list(args, end_span)?));
+        },
+        ".fill" => {
+            let mut exprs = parse_expr_list(args, end_span)?;
+            if exprs.len() != 2 {
+                let span = exprs.get(2).map_or(end_span, |extra| &extra.span);
+                return Err(ParseError::new(ParseErrorKind::Expected("a count and a value"), span));
+            }
+            let value = exprs.remove(1);
+            return data(DataDirective::Fill { count: exprs.remove(0), value });
+        },
+        ".string" => {
+            let text = match args.first() {
+                Some(token) if token.kind == TokenKind::Word && token.text.starts_with('"') => token,
+                Some(token) => return Err(ParseError::new(ParseErrorKind::Expected("a \"string\""), &token.span)),
+                None => return Err(ParseError::new(ParseErrorKind::Expected("a \"string\""), end_span)),
+            };
+            expect_no_args(&args[1..])?;
+            return match literal::parse_string(&text.text) {
+                Ok(mut words) => {
+                    // strings are zero terminated, so a program can find the end
+                    words.push(0);
+                    data(DataDirective::Str(words))
+                },
+                Err(kind) => Err(ParseError::new(kind, &text.span)),
+            };
+        },
+        _ => return Err(ParseError::new(ParseErrorKind::UnknownDirective(name.text.clone()), &name.span)),
+    }
+}
+
+// `end_span` is just past the end of the line, for pointing at anything missing
+fn parse_statement(tokens: &[Token], end_span: &Span) -> Result<Statement, ParseError> {
+    let mut tokens = tokens;
 
-    let arg_fmt = match words[0] {
-        "NOOP" => {
-            vec![]
+    let mut label = None;
+    if tokens.len() >= 2 && tokens[1].kind == TokenKind::Colon {
+        let name = &tokens[0].text;
+        if !expr::is_symbol_token(&tokens[0]) && !expr::is_local_label(name) && !expr::is_numeric_label(name) {
+            return Err(ParseError::new(ParseErrorKind::InvalidLabel(tokens[0].text.clone()),
+                                       &tokens[0].span));
         }
-        "LOAD" => {
-            instruction = Instruction::LOAD(0);
-            vec![Operand::Number(0)]
+        label = Some(Ident::from_token(&tokens[0]));
+        tokens = &tokens[2..];
+    }
+
+    if let Some(colon) = tokens.iter().find(|token| token.kind == TokenKind::Colon) {
+        return Err(unexpected(colon));
+    }
+
+    let first = match tokens.first() {
+        Some(first) if first.kind == TokenKind::Word => first,
+        Some(first) => return Err(unexpected(first)),
+        None => return Ok(Statement { label, body: None }),
+    };
+
+    let body = if first.text.starts_with('.') {
+        parse_directive(first, &tokens[1..], end_span)?
+    } else {
+        StatementBody::Instruction {
+            mnemonic: Ident::from_token(first),
+            operands: parse_operand_list(&tokens[1..], end_span)?,
         }
-        "R2A_LOAD" => {
-            instruction = Instruction::R2A_LOAD(0);
-            vec![Operand::Register(0)]
+    };
+
+    return Ok(Statement { label, body: Some(body) });
+}
+
+enum SymbolValue {
+    // a label, in the section it was defined in
+    Address(i32, Section),
+    Constant(Expr),
+    // given with `ParseOptions::defines` rather than in the source
+    Define(i32),
+    // another name for a register
+    Alias(i32),
+}
+
+struct Symbol {
+    value: SymbolValue,
+    span: Span,
+}
+
+// Everything with a name: labels and constants. Constants are evaluated
+// lazily so they can refer to labels and constants defined further down.
+//
+// Symbols defined in an included file live in that file's namespace, so
+// `mul` in math.aaaasm is `math::mul` everywhere else. Symbols in the main
+// file are global.
+struct SymbolTable {
+    symbols: HashMap<String, Symbol>,
+    // None when the symbol couldn't be evaluated, the error has already been
+    // reported so uses of it shouldn't report it again
+    values: HashMap<String, Option<i32>>,
+    in_progress: Vec<String>,
+    // the namespace of each included file
+    namespaces: HashMap<Rc<str>, String>,
+}
+
+impl SymbolTable {
+    fn new() -> SymbolTable {
+        return SymbolTable {
+            symbols: HashMap::new(),
+            values: HashMap::new(),
+            in_progress: Vec::new(),
+            namespaces: HashMap::new(),
+        };
+    }
+
+    // the namespace of an included file is its name without the extension
+    fn add_namespace(&mut self, file: &Rc<str>) {
+        let namespace = Path::new(&**file).file_stem().unwrap_or_default().to_string_lossy();
+        self.namespaces.insert(file.clone(), namespace.into_owned());
+    }
+
+    // the full name of the symbol being defined by `name`
+    fn qualified(&self, name: &Ident) -> String {
+        return match self.namespaces.get(&name.span.file) {
+            Some(namespace) => format!("{}::{}", namespace, name.name),
+            None => name.name.clone(),
+        };
+    }
+
+    // the full name of the symbol that `name` refers to when written at
+    // `span`. Unqualified names are looked for in the namespace of the file
+    // they're written in first, then in the global namespace.
+    fn resolve(&self, name: &str, span: &Span) -> String {
+        if let Some(namespace) = self.namespaces.get(&span.file) {
+            let qualified = format!("{}::{}", namespace, name);
+            if !name.contains("::") && self.symbols.contains_key(&qualified) {
+                return qualified;
+            }
         }
-        "M2R_LOAD" => {
-            instruction = Instruction::M2R_LOAD(0, 0);
-            vec![Operand::Number(0), Operand::Register(0)]
+        return name.to_string();
+    }
+
+    fn define(&mut self, name: &Ident, value: SymbolValue) -> Result<(), ParseError> {
+        let qualified = self.qualified(name);
+        let kind = match self.symbols.get(&qualified) {
+            Some(Symbol { value: SymbolValue::Define(_), .. }) => ParseErrorKind::AlreadyDefined(qualified),
+            Some(existing) => ParseErrorKind::DuplicateSymbol {
+                name: qualified,
+                first_line: existing.span.line,
+            },
+            None => {
+                self.symbols.insert(qualified, Symbol { value, span: name.span.clone() });
+                return Ok(());
+            },
+        };
+        return Err(ParseError::new(kind, &name.span));
+    }
+
+    fn add_defines(&mut self, defines: &[(String, i32)]) {
+        for (name, value) in defines.iter() {
+            let text = format!("-D {}={}", name, value);
+            let line = SourceLine { file: Rc::from("<command line>"), number: 0, text: Rc::from(text.as_str()) };
+            let span = line.span(3, 3 + name.len());
+            self.symbols.insert(name.clone(), Symbol { value: SymbolValue::Define(*value), span });
         }
-        "M2A_LOAD" => {
-            instruction = Instruction::M2A_LOAD(0);
-            vec![Operand::Number(0)]
+    }
+
+    fn is_defined(&self, name: &str, span: &Span) -> bool {
+        return self.symbols.contains_key(&self.resolve(name, span));
+    }
+
+    fn value(&mut self, name: &str, use_span: &Span, errors: &mut Vec<ParseError>) -> Option<i32> {
+        if let Some(value) = self.values.get(name) {
+            return *value;
         }
-        "A2R_STORE" => {
-            instruction = Instruction::A2R_STORE(0);
-            vec![Operand::Register(0)]
+
+        let (expr, def_span) = match self.symbols.get(name) {
+            Some(Symbol { value: SymbolValue::Address(x, _) | SymbolValue::Define(x), .. }) => return Some(*x),
+            Some(Symbol { value: SymbolValue::Constant(expr), span }) => (expr.clone(), span.clone()),
+            Some(Symbol { value: SymbolValue::Alias(_), .. }) => {
+                errors.push(ParseError::new(ParseErrorKind::AliasInExpression(name.to_string()), use_span));
+                return None;
+            },
+            None => {
+                errors.push(ParseError::new(ParseErrorKind::UndefinedSymbol(name.to_string()), use_span));
+                return None;
+            },
+        };
+
+        if self.in_progress.iter().any(|n| n == name) {
+            errors.push(ParseError::new(ParseErrorKind::CyclicDefinition(name.to_string()), &def_span));
+            return None;
         }
-        "A2M_STORE" => {
-            instruction = Instruction::A2M_STORE(0);
-            vec![Operand::Number(0)]
+
+        self.in_progress.push(name.to_string());
+        let value = self.eval(&expr, errors);
+        self.in_progress.pop();
+
+        self.values.insert(name.to_string(), value);
+        return value;
+    }
+
+    // the register an operand refers to, if it's just the name of an alias
+    fn register(&self, expr: &Expr) -> Option<i32> {
+        let name = match &expr.kind {
+            ExprKind::Symbol(name) => self.resolve(name, &expr.span),
+            _ => return None,
+        };
+        return match self.symbols.get(&name) {
+            Some(Symbol { value: SymbolValue::Alias(r), .. }) => Some(*r),
+            _ => None,
+        };
+    }
+
+    // reports a label in the data section used by `expr`, directly or
+    // through constants, where a jump target is needed. `seen` is the
+    // constants already looked at
+    fn check_target(&self, expr: &Expr, seen: &mut Vec<String>, errors: &mut Vec<ParseError>) -> bool {
+        let mut ok = true;
+        expr.symbols(&mut |name, span| {
+            let name = self.resolve(name, span);
+            match self.symbols.get(&name) {
+                Some(Symbol { value: SymbolValue::Address(_, Section::Data), .. }) => {
+                    errors.push(ParseError::new(ParseErrorKind::DataLabelAsTarget(name), span));
+                    ok = false;
+                },
+                Some(Symbol { value: SymbolValue::Constant(value), .. }) if !seen.contains(&name) => {
+                    seen.push(name);
+                    // point at where the constant is used
+                    let mut inner = Vec::new();
+                    if !self.check_target(value, seen, &mut inner) {
+                        errors.extend(inner.into_iter().map(|err| ParseError::new(err.kind, span)));
+                        ok = false;
+                    }
+                },
+                _ => {},
+            }
+        });
+        return ok;
+    }
+
+    fn eval(&mut self, expr: &Expr, errors: &mut Vec<ParseError>) -> Option<i32> {
+        let mut lookup_errors = Vec::new();
+        let value = expr.eval(&mut |name, span| {
+            let name = self.resolve(name, span);
+            return self.value(&name, span, &mut lookup_errors);
+        }, errors);
+        errors.append(&mut lookup_errors);
+        return value;
+    }
+}
+
+pub fn parse_instruction(s: &str) -> Result<Instruction, ParseError> {
+    let line = SourceLine { file: Rc::from("<input>"), number: 1, text: Rc::from(s) };
+    let tokens = lexer::tokenize(&line).tokens;
+    let statement = parse_statement(&tokens, &span_after(&line, &tokens))?;
+    let (mnemonic, operands) = match statement.body {
+        Some(StatementBody::Instruction { mnemonic, operands }) => (mnemonic, operands),
+        _ => return Err(ParseError::new(ParseErrorKind::MissingInstruction, &line.span(0, s.len()))),
+    };
+
+    let mut errors = Vec::new();
+    return match assemble_instruction(&mnemonic, &operands, REG_NUMBER, &mut SymbolTable::new(), &mut errors) {
+        Some(ins) => Ok(ins),
+        None => Err(errors.remove(0)),
+    };
+}
+
+fn check_register(register: i32, span: &Span, registers: usize, errors: &mut Vec<ParseError>) -> bool {
+    if register < 0 || register as usize >= registers {
+        let kind = ParseErrorKind::RegisterOutOfRange { register, count: registers };
+        errors.push(ParseError::new(kind, span));
+        return false;
+    }
+    return true;
+}
+
+// builds the instruction for a machine with `registers` registers, reporting
+// any problems onto `errors`
+fn assemble_instruction(mnemonic: &Ident, operands: &[OperandExpr], registers: usize,
+                        symbols: &mut SymbolTable, errors: &mut Vec<ParseError>) -> Option<Instruction> {
+    // mnemonics can be written in any case, like registers
+    let upper = mnemonic.name.to_ascii_uppercase();
+    let info = match INSTRUCTIONS.iter().find(|info| info.mnemonic == upper) {
+        Some(info) => info,
+        None => {
+            errors.push(ParseError::new(ParseErrorKind::UnknownMnemonic(mnemonic.name.clone()),
+                                        &mnemonic.span));
+            return None;
+        },
+    };
+
+    if operands.len() != info.operands.len() {
+        let kind = ParseErrorKind::WrongOperandCount {
+            mnemonic: mnemonic.name.clone(),
+            expected: info.operands.len(),
+            found: operands.len(),
+        };
+        // point at the surplus operands, or just past the last operand if some are missing
+        let last = operands.last().map_or(&mnemonic.span, |op| op.span());
+        let span = match operands.get(info.operands.len()) {
+            Some(first_extra) => first_extra.span().join(last),
+            None => Span { start: last.end, end: last.end + 1, ..last.clone() },
+        };
+        errors.push(ParseError::new(kind, &span));
+        return None;
+    }
+
+    let mut values = Vec::new();
+    for (operand, expected) in operands.iter().zip(info.operands.iter()) {
+        if let OperandExpr::Number(expr) = operand {
+            if *expected == OperandKind::Target && !symbols.check_target(expr, &mut Vec::new(), errors) {
+                return None;
+            }
         }
-        "R2M_STORE" => {
-            instruction = Instruction::R2M_STORE(0, 0);
-            vec![Operand::Register(0), Operand::Number(0)]
+        let op = match operand {
+            OperandExpr::Register(r, span) if !check_register(*r, span, registers, errors) => return None,
+            OperandExpr::Register(r, _) => Some(Operand::Register(*r)),
+            OperandExpr::Number(expr) => match symbols.register(expr) {
+                Some(r) => Some(Operand::Register(r)),
+                None => symbols.eval(expr, errors).map(Operand::Number),
+            },
+        };
+        match op {
+            Some(op) if op.fits(*expected) => values.push(op.inner()),
+            Some(op) => {
+                let kind = ParseErrorKind::WrongOperandKind {
+                    mnemonic: mnemonic.name.clone(),
+                    expected: expected.name(),
+                    found: op.kind_name(),
+                };
+                errors.push(ParseError::new(kind, operand.span()));
+                return None;
+            },
+            None => return None,
         }
-        "I_ADD" => {
-            instruction = Instruction::I_ADD(0);
-            vec![Operand::Number(0)]
+    }
+
+    return Instruction::from_parts(info.mnemonic, &values);
+}
+
+pub fn parse_code(s: &str) -> Result<Program, Vec<ParseError>> {
+    return parse_source("<input>", s);
+}
+
+// a line for something given on the command line, for errors to point at
+fn command_line(text: &str) -> SourceLine {
+    return SourceLine { file: Rc::from("<command line>"), number: 1, text: Rc::from(text) };
+}
+
+// checks the size of part of the machine, which can be from 0 to `max`
+fn machine_size(value: i64, what: &'static str, max: usize, span: &Span,
+                errors: &mut Vec<ParseError>) -> Option<usize> {
+    if value < 0 || value > max as i64 {
+        errors.push(ParseError::new(ParseErrorKind::MachineOutOfRange { what, value, max }, span));
+        return None;
+    }
+    return Some(value as usize);
+}
+
+// the machine asked for by `.machine`, with anything given in the options in
+// place of what it says
+fn machine_config(statements: &[Statement], options: &ParseOptions, symbols: &mut SymbolTable,
+                  errors: &mut Vec<ParseError>) -> MachineConfig {
+    let mut machine = MachineConfig::default();
+    let mut first: Option<&Span> = None;
+    for statement in statements.iter() {
+        let (registers, memory, overflow) = match &statement.body {
+            Some(StatementBody::Machine { registers, memory, overflow }) => (registers, memory, overflow),
+            _ => continue,
+        };
+        if let Some(first) = first {
+            let kind = ParseErrorKind::DuplicateDirective { directive: ".machine".to_string(), first_line: first.line };
+            errors.push(ParseError::new(kind, &registers.span));
+            continue;
         }
-        "R_ADD" => {
-            instruction = Instruction::R_ADD(0);
-            vec![Operand::Register(0)]
+        first = Some(&registers.span);
+        machine.overflow = overflow.unwrap_or_default();
+
+        let sizes = [(registers, "registers", MAX_REGISTERS, &mut machine.registers),
+                     (memory, "words of memory", MAX_MEMORY, &mut machine.memory)];
+        for (expr, what, max, size) in sizes {
+            if let Some(value) = symbols.eval(expr, errors).and_then(|value| {
+                return machine_size(value as i64, what, max, &expr.span, errors);
+            }) {
+                *size = value;
+            }
         }
-        "JUMP" => {
-            instruction = Instruction::JUMP(0);
-            vec![Operand::Number(0)]
+    }
+
+    let sizes = [(options.registers, "registers", MAX_REGISTERS, &mut machine.registers),
+                 (options.memory, "words of memory", MAX_MEMORY, &mut machine.memory)];
+    for (option, what, max, size) in sizes {
+        if let Some(value) = option {
+            let line = command_line(&value.to_string());
+            let value = i64::try_from(value).unwrap_or(i64::MAX);
+            if let Some(value) = machine_size(value, what, max, &line.span(0, line.text.len()), errors) {
+                *size = value;
+            }
         }
-        "JUMP_NEG" => {
-            instruction = Instruction::JUMP_NEG(0);
-            vec![Operand::Number(0)]
+    }
+    if let Some(overflow) = options.overflow {
+        machine.overflow = overflow;
+    }
+    return machine;
+}
+
+/// Settings for a parse that don't come from the source itself.
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// Constants defined before the first line, as if by `.equ`.
+    pub defines: Vec<(String, i32)>,
+    /// Where to start the program, which overrides any `.entry`. Written
+    /// like the operand of `.entry`.
+    pub entry: Option<String>,
+    /// The number of registers, which overrides any `.machine`.
+    pub registers: Option<usize>,
+    /// The size of memory in words, which overrides any `.machine`.
+    pub memory: Option<usize>,
+    /// What happens when arithmetic overflows, which overrides any `.machine`.
+    pub overflow: Option<OverflowMode>,
+}
+
+/// Parses a define given as `NAME=VALUE`, or just `NAME` for a value of 1.
+/// The value can be written in any way a number can in the source.
+pub fn parse_define(s: &str) -> Result<(String, i32), String> {
+    let (name, value) = s.split_once('=').unwrap_or((s, "1"));
+    if !expr::is_symbol_name(name) {
+        return Err(ParseErrorKind::InvalidLabel(name.to_string()).to_string());
+    }
+    return match literal::parse_number(value) {
+        Ok(value) => Ok((name.to_string(), value)),
+        Err(kind) => Err(kind.to_string()),
+    };
+}
+
+// a block of data whose address and size are known, but whose values may
+// not have been worked out yet
+struct DataLayout {
+    address: i64,
+    len: usize,
+    span: Span,
+    // whether it fits in memory, set by check_data_layout
+    fits: bool,
+}
+
+// checks that every block of data fits in `memory` words without overlapping
+// another
+fn check_data_layout(layout: &mut [DataLayout], memory: usize, errors: &mut Vec<ParseError>) {
+    let mut in_range:Vec<_> = Vec::new();
+    for block in layout.iter_mut() {
+        block.fits = block.address >= 0 && block.address + block.len as i64 <= memory as i64;
+        if !block.fits {
+            let kind = ParseErrorKind::DataOutOfRange { address: block.address, len: block.len };
+            errors.push(ParseError::new(kind, &block.span));
+        } else if block.len > 0 {
+            in_range.push(&*block);
         }
-        _ => {
-            return Err(format!("Illegal Instruction: {} does not match any known instruction", words[0]).to_string())
+    }
+
+    in_range.sort_by_key(|block| block.address);
+    for pair in in_range.windows(2) {
+        let (first, second) = (pair[0], pair[1]);
+        if first.address + first.len as i64 > second.address {
+            // blame whichever was written later in the file
+            let (earlier, later) = if first.span.line <= second.span.line {
+                (first, second)
+            } else {
+                (second, first)
+            };
+            let kind = ParseErrorKind::DataOverlap {
+                address: second.address as usize,
+                first_line: earlier.span.line,
+            };
+            errors.push(ParseError::new(kind, &later.span));
         }
-    };
+    }
+}
 
-    if !matching_operand_formats(&ops, &arg_fmt) {
-        return Err(format!("Bad arguments passed to {}", words[0]).to_string());
-    }
-
-    return Ok(match instruction {
-        Instruction::NOOP() => instruction,
-        Instruction::LOAD(_) => Instruction::LOAD(ops[0].inner()),
-        Instruction::R2A_LOAD(_) => Instruction::R2A_LOAD(ops[0].inner()),
-        Instruction::M2R_LOAD(_, _) => Instruction::M2R_LOAD(ops[0].inner(), ops[1].inner()),
-        Instruction::M2A_LOAD(_) => Instruction::M2A_LOAD(ops[0].inner()),
-        Instruction::A2R_STORE(_) => Instruction::A2R_STORE(ops[0].inner()),
-        Instruction::A2M_STORE(_) => Instruction::A2M_STORE(ops[0].inner()),
-        Instruction::R2M_STORE(_, _) => Instruction::R2M_STORE(ops[0].inner(), ops[1].inner()),
-        Instruction::I_ADD(_) => Instruction::I_ADD(ops[0].inner()),
-        Instruction::R_ADD(_) => Instruction::R_ADD(ops[0].inner()),
-        Instruction::JUMP(_) => Instruction::JUMP(ops[0].inner()),
-        Instruction::JUMP_NEG(_) => Instruction::JUMP_NEG(ops[0].inner()),
-    });
+/// Parses a whole program. `file_name` is used to label errors and to find
+/// the files it includes, which are read from disk.
+///
+/// A bad line doesn't stop parsing, the parser carries on from the next line
+/// so that every error in the file is reported at once.
+///
+/// Instructions go in the text section, which is where a program starts.
+/// `.data` switches to the data section, where `.word`, `.fill` and
+/// `.string` lay out the initial contents of memory from address 0 onwards,
+/// or from wherever `.org` says. `.text` switches back. Labels in the data
+/// section stand for the memory address of the data that follows them.
+///
+/// `.include "path"` reads in another file, relative to the one including
+/// it. Labels and constants in an included file belong to its namespace,
+/// named after the file: `mul` in `lib/math.aaaasm` is `math::mul` from
+/// anywhere else. Macros are shared between every file.
+///
+/// The program starts at the first instruction, or wherever `.entry`
+/// says. It runs on a machine with 4 registers and 1024 words of memory,
+/// unless `.machine REGISTERS, MEMORY` asks for another size. Arithmetic
+/// that overflows stops the program, unless a third operand to `.machine`
+/// says `wrapping` or `saturating`.
+///
+/// A label starting with a dot, like `.loop`, is local to the last global
+/// label. Numeric labels like `1:` can be defined many times, `1f` refers to
+/// the next one and `1b` to the last.
+///
+/// Mnemonics and registers can be written in any case, while names of
+/// labels, constants, macros and aliases are case sensitive. `.alias NAME rN`
+/// gives a register another name, which can be used anywhere the register
+/// could.
+///
+/// Lines between `.if EXPR` and `.endif` are only assembled if EXPR isn't
+/// zero, and `.ifdef NAME` or `.ifndef NAME` check whether NAME has been
+/// defined. Either can have an `.else`. The condition can only use
+/// constants defined above it, or given in `ParseOptions::defines`.
+pub fn parse_source(file_name: &str, s: &str) -> Result<Program, Vec<ParseError>> {
+    return parse_source_with(file_name, s, &ParseOptions::default(),
+                             &mut |path| std::fs::read_to_string(path));
+}
+
+/// Like `parse_source`, but with `options`, and included files are read
+/// with `load`.
+pub fn parse_source_with(file_name: &str, s: &str, options: &ParseOptions,
+                         load: &mut FileLoader) -> Result<Program, Vec<ParseError>> {
+    let tree = SyntaxTree::parse(file_name, s);
+    return parse_tree(file_name, &tree, options, load, &mut IncludeCache::default());
 }
 
-pub fn parse_code(s: &str) -> Result<Vec<Instruction>, String> {
-    // comments in AAAASM will begin with a :) or :(
-    let lines:Vec<_> = s.split('\n').collect();
+// everything after lexing the root file, files in `includes` aren't loaded
+// again
+fn parse_tree(file_name: &str, tree: &SyntaxTree, options: &ParseOptions, load: &mut FileLoader,
+              includes: &mut IncludeCache) -> Result<Program, Vec<ParseError>> {
+    let mut errors = Vec::new();
 
-    let mut instructions = Vec::new();
+    // included files are read as they're reached, and their namespaces
+    // added to the symbols for conditions then
+    let mut includer = Includer::new(file_name, load, includes);
+    let mut conditions = SymbolTable::new();
+    conditions.add_defines(&options.defines);
+    let mut statements = macros::expand_lines(&tree.lines, conditions, &mut includer, &mut errors);
+    let files = includer.files;
+
+    let mut symbols = SymbolTable::new();
+    for file in files.iter().skip(1) {
+        symbols.add_namespace(file);
+    }
+    symbols.add_defines(&options.defines);
+    locals::resolve_local_labels(&mut statements, &mut symbols, &mut errors);
 
-    for (line_num, line) in lines.iter().enumerate() {
-        if !(line.starts_with(":)") || line.starts_with(":(") || *line == "") {
-            match parse_instruction(line){
-                Ok(ins) => instructions.push(ins),
-                Err(err) => return Err(format!("Error parsing line {}, error given: {}",
-                                       line_num+1, err)),
+    // constants first, so that .org and .fill can use them even if they
+    // are defined further down
+    for statement in statements.iter() {
+        let defined = match &statement.body {
+            Some(StatementBody::Constant { name, value }) => symbols.define(name, SymbolValue::Constant(value.clone())),
+            Some(StatementBody::Alias { name, register, .. }) => symbols.define(name, SymbolValue::Alias(*register)),
+            _ => continue,
+        };
+        if let Err(err) = defined {
+            errors.push(err);
+        }
+    }
+
+    // registers and data can only be checked once the size of the machine
+    // is known
+    let machine = machine_config(&statements, options, &mut symbols, &mut errors);
+    for statement in statements.iter() {
+        if let Some(StatementBody::Alias { register, register_span, .. }) = &statement.body {
+            check_register(*register, register_span, machine.registers, &mut errors);
+        }
+    }
+
+    // then work out where every instruction and piece of data goes, so that
+    // operands can refer to labels defined further down the file
+    let mut section = Section::Text;
+    let mut ins_count = 0;
+    let mut data_address: i64 = 0;
+    let mut layout = Vec::new();
+
+    for statement in statements.iter() {
+        if let Some(label) = &statement.label {
+            let address = match section {
+                Section::Text => ins_count,
+                Section::Data => data_address as i32,
+            };
+            if let Err(err) = symbols.define(label, SymbolValue::Address(address, section)) {
+                errors.push(err);
             }
         }
+
+        let body = match &statement.body {
+            Some(body) => body,
+            None => continue,
+        };
+
+        let wrong_section = |what: &Ident, section: &'static str| {
+            let kind = ParseErrorKind::WrongSection { what: what.name.clone(), section };
+            return ParseError::new(kind, &what.span);
+        };
+
+        let (directive, data) = match body {
+            StatementBody::Instruction { mnemonic, .. } => {
+                if section == Section::Data {
+                    errors.push(wrong_section(mnemonic, "text"));
+                }
+                ins_count += 1;
+                continue;
+            },
+            StatementBody::Section(new_section) => {
+                section = *new_section;
+                continue;
+            },
+            StatementBody::Org(address) => {
+                section = Section::Data;
+                if let Some(address) = symbols.eval(address, &mut errors) {
+                    data_address = address as i64;
+                }
+                continue;
+            },
+            StatementBody::Constant { .. } | StatementBody::Alias { .. } | StatementBody::Entry(_)
+                | StatementBody::Machine { .. } => continue,
+            StatementBody::Data { directive, data } => (directive, data),
+        };
+
+        if section == Section::Text {
+            errors.push(wrong_section(directive, "data"));
+        }
+
+        let len = match data {
+            DataDirective::Words(values) => values.len(),
+            DataDirective::Str(words) => words.len(),
+            DataDirective::Fill { count, .. } => match symbols.eval(count, &mut errors) {
+                Some(n) if n as i64 > MAX_MEMORY as i64 => {
+                    let kind = ParseErrorKind::FillTooLarge { count: n, max: MAX_MEMORY };
+                    errors.push(ParseError::new(kind, &count.span));
+                    0
+                },
+                Some(n) if n >= 0 => n as usize,
+                Some(_) => {
+                    errors.push(ParseError::new(ParseErrorKind::Expected("a count of zero or more"),
+                                                &count.span));
+                    0
+                },
+                None => 0,
+            },
+        };
+
+        layout.push(DataLayout { address: data_address, len, span: directive.span.clone(), fits: false });
+        data_address += len as i64;
     }
 
-    return Ok(instructions);
-}
+    check_data_layout(&mut layout, machine.memory, &mut errors);
+
+    // an entry point from the options replaces the one in the source
+    let entry_option = options.entry.as_ref().and_then(|target| {
+        let line = command_line(target);
+        let tokens = lexer::tokenize(&line).tokens;
+        return match parse_expr_list(&tokens, &span_after(&line, &tokens)) {
+            Ok(mut exprs) if exprs.len() == 1 => Some(exprs.remove(0)),
+            Ok(exprs) => {
+                errors.push(ParseError::new(ParseErrorKind::Expected("a single target"), &exprs[1].span));
+                None
+            },
+            Err(err) => {
+                errors.push(err);
+                None
+            },
+        };
+    });
 
+    // finally evaluate everything and build the program
+    let mut instructions = Vec::new();
+    let mut source_map = Vec::new();
+    let mut data = Vec::new();
+    let mut entry: Option<&Expr> = None;
+    let mut layout = layout.into_iter();
+
+    for statement in statements.iter() {
+        let data_directive = match &statement.body {
+            Some(StatementBody::Instruction { mnemonic, operands }) => {
+                if let Some(ins) = assemble_instruction(mnemonic, operands, machine.registers, &mut symbols,
+                                                        &mut errors) {
+                    // instructions from a macro belong to the line that used it
+                    let root = mnemonic.span.root();
+                    instructions.push(ins);
+                    source_map.push(SourceLocation {
+                        file: root.file.to_string(),
+                        line: root.line,
+                        text: root.line_text.trim().to_string(),
+                    });
+                }
+                continue;
+            },
+            // evaluated here as well as where they're used so that errors
+            // in unused constants still get reported
+            Some(StatementBody::Constant { name, .. }) => {
+                let name_span = &name.span;
+                let name = symbols.qualified(name);
+                symbols.value(&name, name_span, &mut errors);
+                continue;
+            },
+            Some(StatementBody::Data { data, .. }) => data,
+            Some(StatementBody::Entry(target)) => {
+                match entry {
+                    Some(first) => {
+                        let kind = ParseErrorKind::DuplicateDirective {
+                            directive: ".entry".to_string(),
+                            first_line: first.span.line,
+                        };
+                        errors.push(ParseError::new(kind, &target.span));
+                    },
+                    None => entry = Some(target),
+                }
+                continue;
+            },
+            _ => continue,
+        };
+
+        let block = layout.next().unwrap();
+        let words = match data_directive {
+            DataDirective::Words(values) => {
+                values.iter().map(|value| symbols.eval(value, &mut errors).unwrap_or(0)).collect()
+            },
+            DataDirective::Fill { value, .. } => {
+                let value = symbols.eval(value, &mut errors).unwrap_or(0);
+                // a block that doesn't fit has already been reported, and
+                // could be far too big to build
+                vec![value; if block.fits { block.len } else { 0 }]
+            },
+            DataDirective::Str(words) => words.clone(),
+        };
+        data.push(DataBlock { address: block.address as usize, words });
+    }
+
+    if let Some(target) = entry_option.as_ref() {
+        entry = Some(target);
+    }
+    let entry = match entry {
+        None => 0,
+        Some(target) if !symbols.check_target(target, &mut Vec::new(), &mut errors) => 0,
+        Some(target) => match symbols.eval(target, &mut errors) {
+            Some(x) if x >= 0 && (x as usize) < instructions.len().max(1) => x as usize,
+            Some(x) => {
+                errors.push(ParseError::new(ParseErrorKind::EntryOutOfRange(x), &target.span));
+                0
+            },
+            None => 0,
+        },
+    };
+
+    if !errors.is_empty() {
+        // errors come from different passes, keep everything in file order
+        errors.sort_by_key(|err| {
+            let root = err.span.root();
+            let file = files.iter().position(|file| *file == root.file);
+            return (file, root.line, root.start);
+        });
+        // conditions are evaluated before everything else, so errors in the
+        // constants they use can be found twice
+        errors.dedup();
+        return Err(errors);
+    }
+
+    return Ok(Program { instructions, data, source_map, machine: MachineConfig { initial_pc: entry, ..machine } });
+}
@@ -0,0 +1,188 @@
+// A lossless view of a file for tools like editors and the formatter. Every
+// byte of the source is in exactly one token, whitespace and comments
+// included, and each line remembers how it ended, so printing the tree
+// gives back the file exactly as it was read. Parsing a program starts
+// from this tree too.
+
+use std::cell::OnceCell;
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::parser::lexer::{self, LexedLine, SourceLine, Token, TokenKind};
+use crate::parser::{parse_statement, ParseError, Span, Statement};
+
+/// One line of source and every token in it.
+#[derive(Debug, Clone)]
+pub struct SyntaxLine {
+    /// The tokens on the line, which add up to the whole line.
+    pub tokens: Vec<Token>,
+    /// `"\n"` or `"\r\n"`, or nothing (or a stray `"\r"`) for the last line.
+    pub ending: &'static str,
+    /// The byte offset of the start of the line in the file.
+    pub offset: usize,
+    pub(super) source: SourceLine,
+    // the statement on the line, kept so that parsing the program again
+    // doesn't parse lines that haven't changed
+    statement: OnceCell<Result<Statement, ParseError>>,
+}
+
+// the cached statement is left out, it only depends on the tokens
+impl PartialEq for SyntaxLine {
+    fn eq(&self, other: &SyntaxLine) -> bool {
+        return self.tokens == other.tokens && self.ending == other.ending
+            && self.offset == other.offset && self.source == other.source;
+    }
+}
+
+impl Eq for SyntaxLine {}
+
+impl SyntaxLine {
+    pub fn number(&self) -> usize {
+        return self.source.number;
+    }
+
+    /// The line without its ending.
+    pub fn text(&self) -> &str {
+        return &self.source.text;
+    }
+
+    /// The tokens that mean something to the program.
+    pub fn significant(&self) -> impl Iterator<Item = &Token> {
+        return self.tokens.iter().filter(|token| !token.kind.is_trivia());
+    }
+
+    pub fn comment(&self) -> Option<&Token> {
+        return self.tokens.iter().find(|token| matches!(token.kind, TokenKind::Comment(_)));
+    }
+
+    /// Whether there is nothing on the line but whitespace.
+    pub fn is_blank(&self) -> bool {
+        return self.tokens.iter().all(|token| token.kind == TokenKind::Whitespace);
+    }
+
+    pub(super) fn lexed(&self) -> LexedLine {
+        return LexedLine {
+            tokens: self.significant().cloned().collect(),
+            comment: self.comment().cloned(),
+        };
+    }
+
+    // the statement on the line, `tokens` and `end_span` are what
+    // `parse_statement` would be given for it
+    pub(super) fn statement(&self, tokens: &[Token], end_span: &Span) -> Result<Statement, ParseError> {
+        return self.statement.get_or_init(|| parse_statement(tokens, end_span)).clone();
+    }
+}
+
+/// Every line of a file, see `SyntaxTree::parse`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxTree {
+    /// Shared, so that the parser can hold on to lines without copying them.
+    pub lines: Vec<Rc<SyntaxLine>>,
+}
+
+impl SyntaxTree {
+    /// Splits `s` into lines and tokens. This can't fail, anything the
+    /// parser wouldn't understand is still a token. `file_name` is only used
+    /// in the spans of the tokens.
+    pub fn parse(file_name: &str, s: &str) -> SyntaxTree {
+        return SyntaxTree::parse_file(&Rc::from(file_name), s);
+    }
+
+    pub(super) fn parse_file(file: &Rc<str>, s: &str) -> SyntaxTree {
+        return SyntaxTree { lines: split_lines(file, s, 1, 0) };
+    }
+
+    // the line that the byte at `offset` is in, or the last line if it's
+    // past the end
+    fn line_at(&self, offset: usize) -> usize {
+        return self.lines.partition_point(|line| line.offset <= offset).saturating_sub(1);
+    }
+
+    /// Replaces the bytes in `range` with `replacement`, like
+    /// `String::replace_range`. Only the lines the edit touches are lexed
+    /// again, the lines after them are just moved, along with the statements
+    /// already parsed from them. Returns the indices of
+    /// the lines that were lexed again, in the edited tree.
+    ///
+    /// Panics if `range` is out of bounds or isn't on character boundaries.
+    pub fn edit(&mut self, range: Range<usize>, replacement: &str) -> Range<usize> {
+        let (first, last) = (self.line_at(range.start), self.line_at(range.end));
+        let start = self.lines[first].offset;
+        let mut text: String = self.lines[first..=last].iter().map(|line| line.to_string()).collect();
+        text.replace_range(range.start - start..range.end - start, replacement);
+
+        let file = self.lines[first].source.file.clone();
+        let mut lines = split_lines(&file, &text, first + 1, start);
+        // the edit ends inside the last line it touches, so if that line has
+        // a newline the new text does too, and the empty line split off
+        // after it is really the next line
+        if last + 1 < self.lines.len() {
+            lines.pop();
+        }
+
+        let new_last = first + lines.len();
+        let line_delta = new_last as isize - (last + 1) as isize;
+        let offset_delta = replacement.len() as isize - range.len() as isize;
+        self.lines.splice(first..=last, lines);
+        for line in self.lines[new_last..].iter_mut() {
+            let line = Rc::make_mut(line);
+            line.offset = (line.offset as isize + offset_delta) as usize;
+            if line_delta == 0 {
+                continue;
+            }
+            let number = (line.source.number as isize + line_delta) as usize;
+            line.source.number = number;
+            for token in line.tokens.iter_mut() {
+                token.span.line = number;
+            }
+            match line.statement.get_mut() {
+                Some(Ok(statement)) => statement.set_line(number),
+                Some(Err(err)) => err.span.line = number,
+                None => {},
+            }
+        }
+        return first..new_last;
+    }
+}
+
+impl fmt::Display for SyntaxLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for token in self.tokens.iter() {
+            write!(f, "{}", token.text)?;
+        }
+        return write!(f, "{}", self.ending);
+    }
+}
+
+// lexes `s`, whose first line is numbered `number` and starts at `offset` in
+// the file
+fn split_lines(file: &Rc<str>, s: &str, number: usize, mut offset: usize) -> Vec<Rc<SyntaxLine>> {
+    let end = offset + s.len();
+    let mut lines = Vec::new();
+    for (idx, raw) in s.split('\n').enumerate() {
+        let has_newline = offset + raw.len() < end;
+        let (text, ending) = match (raw.strip_suffix('\r'), has_newline) {
+            (Some(text), true) => (text, "\r\n"),
+            (Some(text), false) => (text, "\r"),
+            (None, true) => (raw, "\n"),
+            (None, false) => (raw, ""),
+        };
+
+        let source = SourceLine { file: file.clone(), number: number + idx, text: Rc::from(text) };
+        let tokens = lexer::tokenize_all(&source);
+        lines.push(Rc::new(SyntaxLine { tokens, ending, offset, source, statement: OnceCell::new() }));
+        offset += raw.len() + 1;
+    }
+    return lines;
+}
+
+impl fmt::Display for SyntaxTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in self.lines.iter() {
+            write!(f, "{}", line)?;
+        }
+        return Ok(());
+    }
+}
@@ -1,5 +1,22 @@
 #[cfg(test)]
 use crate::parser::*;
+#[cfg(test)]
+use crate::interpreter::{MachineConfig, OperandKind, OverflowMode, INSTRUCTIONS, MAX_MEMORY, REG_NUMBER};
+#[cfg(test)]
+use proptest::prelude::*;
+
+// on failure, also gives the index of the operand that couldn't be parsed
+#[cfg(test)]
+fn parse_operands(operands: &Vec<&str>) -> Result<Vec<Operand>, (usize, ParseErrorKind)> {
+    let mut ops = Vec::new();
+    for (idx, word) in operands.iter().enumerate() {
+        match word.parse::<Operand>() {
+            Ok(op) => ops.push(op),
+            Err(err) => return Err((idx, err)),
+        }
+    }
+    return Ok(ops);
+}
 
 #[test]
 fn parse_one_number_test() {
@@ -51,3 +68,658 @@ fn parse_instruction2_test() {
     let goal = Ok(Instruction::M2R_LOAD(200, 2));
     assert_eq!(parse_instruction(input), goal);
 }
+
+#[test]
+fn parse_labels_test() {
+    let input = "start:\nLOAD 10\nloop: I_ADD -1\nJUMP_NEG end\nJUMP loop\nend:\nJUMP start";
+    let goal = Ok(vec![Instruction::LOAD(10), Instruction::I_ADD(-1),
+                       Instruction::JUMP_NEG(4), Instruction::JUMP(1),
+                       Instruction::JUMP(0)]);
+    assert_eq!(parse_code(input).map(|program| program.instructions), goal);
+}
+
+#[test]
+fn parse_undefined_label_fail() {
+    let input = "LOAD 10\nJUMP nowhere";
+    let err = &parse_code(input).unwrap_err()[0];
+    assert_eq!(err.kind, ParseErrorKind::UndefinedSymbol("nowhere".to_string()));
+    assert_eq!((err.span.line, err.span.start, err.span.end), (2, 5, 12));
+}
+
+#[test]
+fn parse_duplicate_label_fail() {
+    let input = "here:\nLOAD 10\nhere: NOOP";
+    let err = &parse_code(input).unwrap_err()[0];
+    assert_eq!(err.kind, ParseErrorKind::DuplicateSymbol { name: "here".to_string(), first_line: 1 });
+    assert_eq!(err.span.line, 3);
+}
+
+#[test]
+fn parse_error_kinds_test() {
+    let kind = |s| parse_instruction(s).unwrap_err().kind;
+    assert!(matches!(kind("LAOD 10"), ParseErrorKind::UnknownMnemonic(_)));
+    assert!(matches!(kind("LOAD 10 20"), ParseErrorKind::WrongOperandCount { expected: 1, found: 2, .. }));
+    assert!(matches!(kind("LOAD r1"), ParseErrorKind::WrongOperandKind { expected: "number", .. }));
+    assert!(matches!(kind("LOAD 1o"), ParseErrorKind::MalformedNumber(_)));
+    assert!(matches!(kind("R_ADD r2x"), ParseErrorKind::MalformedRegister(_)));
+}
+
+#[test]
+fn render_error_test() {
+    let err = &parse_source("prog.aaaasm", "LOAD 10\nR_ADD r2x").unwrap_err()[0];
+    assert_eq!(err.render(), "error: `r2x` is not a valid register\n \
+                              --> prog.aaaasm:2:7\n  |\n2 | R_ADD r2x\n  |       ^^^");
+}
+
+#[test]
+fn parse_reports_every_error_test() {
+    let input = "LAOD 10\nI_ADD 5\nx:\nR_ADD 3\nx: JUMP y\nNOOP";
+    let lines:Vec<_> = parse_code(input).unwrap_err().iter().map(|err| err.span.line).collect();
+    assert_eq!(lines, vec![1, 4, 5, 5]);
+}
+
+#[test]
+fn parse_whitespace_test() {
+    let input = "LOAD  10\r\n\tloop:\tI_ADD   -1 :) count down\r\n  :( indented comment\r\n  JUMP_NEG loop\r\n";
+    let goal = Ok(vec![Instruction::LOAD(10), Instruction::I_ADD(-1),
+                       Instruction::JUMP_NEG(1)]);
+    assert_eq!(parse_code(input).map(|program| program.instructions), goal);
+}
+
+#[test]
+fn tokenize_comment_test() {
+    let line = SyntaxTree::parse("<input>", "end: NOOP:) done").lines[0].lexed();
+    let texts:Vec<_> = line.tokens.iter().map(|token| token.text.as_str()).collect();
+    assert_eq!(texts, vec!["end", ":", "NOOP"]);
+    assert_eq!(line.comment.unwrap().span.start, 9);
+}
+
+#[test]
+fn parse_number_literals_test() {
+    let ops = parse_operands(&vec!["0x1F", "0b1010", "0o17", "'A'", "' '", "'\\n'", "1_000",
+                                   "-0x10", "0xFFFFFFFF", "-2147483648"]);
+    let goal:Vec<_> = [31, 10, 15, 65, 32, 10, 1000, -16, -1, i32::MIN].iter()
+        .map(|x| Operand::Number(*x)).collect();
+    assert_eq!(ops, Ok(goal));
+}
+
+#[test]
+fn parse_number_literals_fail() {
+    assert_eq!(literal::parse_number("2147483648"),
+               Err(ParseErrorKind::NumberOutOfRange("2147483648".to_string())));
+    assert_eq!(literal::parse_number("0x1_0000_0000"),
+               Err(ParseErrorKind::NumberOutOfRange("0x1_0000_0000".to_string())));
+    for bad in ["0x", "0b102", "_1", "1_", "'AB'", "''", "'A"] {
+        assert_eq!(literal::parse_number(bad), Err(ParseErrorKind::MalformedNumber(bad.to_string())));
+    }
+}
+
+#[test]
+fn parse_char_with_spaces_test() {
+    assert_eq!(parse_instruction("LOAD ' ' :) a space"), Ok(Instruction::LOAD(32)));
+    assert_eq!(parse_instruction("LOAD ':'"), Ok(Instruction::LOAD(58)));
+}
+
+#[test]
+fn parse_constants_test() {
+    let input = ".equ BUF 100\n.equ SIZE END - BUF\nM2A_LOAD BUF+4\nM2R_LOAD (BUF + SIZE*2-1) r1\n\
+                 A2M_STORE -(BUF / 3)\nJUMP start + 1\nstart: .equ END 110";
+    let goal = Ok(vec![Instruction::M2A_LOAD(104), Instruction::M2R_LOAD(119, 1),
+                       Instruction::A2M_STORE(-33), Instruction::JUMP(5)]);
+    assert_eq!(parse_code(input).map(|program| program.instructions), goal);
+}
+
+#[test]
+fn parse_constants_fail() {
+    let input = ".equ A B + 1\n.equ B A * 2\n.equ C 1 / 0\nLOAD A\nLOAD MISSING + 1\nLOAD 0x7FFFFFFF + 1\nLOAD (1 + 2";
+    let kinds:Vec<_> = parse_code(input).unwrap_err().into_iter().map(|err| (err.span.line, err.kind)).collect();
+    assert_eq!(kinds, vec![(1, ParseErrorKind::CyclicDefinition("A".to_string())),
+                           (3, ParseErrorKind::DivisionByZero),
+                           (5, ParseErrorKind::UndefinedSymbol("MISSING".to_string())),
+                           (6, ParseErrorKind::ExpressionOverflow),
+                           (7, ParseErrorKind::UnclosedParen)]);
+}
+
+#[test]
+fn parse_data_test() {
+    let input = "M2A_LOAD table + 1\nJUMP end\n.data\ntable: .word 5, SIZE * 2, 'x'\n\
+                 .org 0x10\nmsg: .string \"hi, \\\"you\\\"\"\n.fill SIZE, -1\n\
+                 .equ SIZE 3\n.text\nend: LOAD msg";
+    let program = parse_code(input).unwrap();
+    assert_eq!(program.instructions, vec![Instruction::M2A_LOAD(1), Instruction::JUMP(2),
+                                          Instruction::LOAD(16)]);
+    assert_eq!(program.data, vec![
+        DataBlock { address: 0, words: vec![5, 6, 120] },
+        DataBlock { address: 16, words: vec![104, 105, 44, 32, 34, 121, 111, 117, 34, 0] },
+        DataBlock { address: 26, words: vec![-1, -1, -1] },
+    ]);
+}
+
+#[test]
+fn parse_data_fail() {
+    let input = ".word 1\n.data\n.org 10\n.fill 5, 0\n.org 12\n.word 1, 2\n\
+                 NOOP\n.org 1020\n.string \"hello\"";
+    let kinds:Vec<_> = parse_code(input).unwrap_err().into_iter().map(|err| (err.span.line, err.kind)).collect();
+    assert_eq!(kinds, vec![
+        (1, ParseErrorKind::WrongSection { what: ".word".to_string(), section: "data" }),
+        (6, ParseErrorKind::DataOverlap { address: 12, first_line: 4 }),
+        (7, ParseErrorKind::WrongSection { what: "NOOP".to_string(), section: "text" }),
+        (9, ParseErrorKind::DataOutOfRange { address: 1020, len: 6 }),
+    ]);
+}
+
+#[test]
+fn parse_data_label_target_fail() {
+    let input = ".data\nt: .word 1\n.equ T t + 1\n.text\nJUMP t\nJUMP_NEG T\nLOAD t\n.entry t";
+    let errors = parse_code(input).unwrap_err();
+    let found:Vec<_> = errors.iter().map(|err| (err.span.line, err.span.start, err.kind.clone())).collect();
+    let kind = ParseErrorKind::DataLabelAsTarget("t".to_string());
+    assert_eq!(found, vec![(5, 5, kind.clone()), (6, 9, kind.clone()), (8, 7, kind.clone())]);
+    assert_eq!(errors[0].kind.to_string(), "`t` is the address of some data, not an instruction to jump to");
+
+    let options = ParseOptions { entry: Some("t".to_string()), ..ParseOptions::default() };
+    let errors = parse_source_with("<input>", ".data\nt: .word 1\n.text\nNOOP", &options,
+                                   &mut |path| std::fs::read_to_string(path)).unwrap_err();
+    assert_eq!(errors[0].kind, kind);
+    assert_eq!(&*errors[0].span.file, "<command line>");
+}
+
+#[test]
+fn parse_macros_test() {
+    let input = ".macro copy from, to\n    R2A_LOAD from\n    A2R_STORE to\n.endm\n\
+                 .macro countdown n\n    LOAD -(n)\nloop: I_ADD 1\n    JUMP_NEG loop\n.endm\n\
+                 .macro twice\n    countdown 2\n    countdown 3 - 1\n.endm\n\
+                 start: copy r1, r2\ntwice\nJUMP start";
+    let goal = Ok(vec![Instruction::R2A_LOAD(1), Instruction::A2R_STORE(2),
+                       Instruction::LOAD(-2), Instruction::I_ADD(1), Instruction::JUMP_NEG(3),
+                       Instruction::LOAD(-2), Instruction::I_ADD(1), Instruction::JUMP_NEG(6),
+                       Instruction::JUMP(0)]);
+    assert_eq!(parse_code(input).map(|program| program.instructions), goal);
+}
+
+#[test]
+fn parse_macro_errors_test() {
+    let input = ".macro bad x\n    LAOD x\n.endm\nNOOP\nbad 1\nbad 1, 2\n.endm\n.macro forever\nforever\n.endm\nforever";
+    let errors = parse_code(input).unwrap_err();
+    let kinds:Vec<_> = errors.iter().map(|err| (err.span.line, err.kind.clone())).collect();
+    assert_eq!(kinds, vec![
+        (2, ParseErrorKind::UnknownMnemonic("LAOD".to_string())),
+        (6, ParseErrorKind::WrongOperandCount { mnemonic: "bad".to_string(), expected: 1, found: 2 }),
+        (7, ParseErrorKind::Unmatched(".endm".to_string())),
+        (9, ParseErrorKind::MacroRecursion("forever".to_string())),
+    ]);
+    assert_eq!(errors[0].render(), "error: unknown mnemonic `LAOD`\n \
+                                    --> <input>:2:5\n  |\n2 |     LAOD x\n  |     ^^^^\n\
+                                    note: in this expansion of macro `bad`\n \
+                                    --> <input>:5:1\n  |\n5 | bad 1\n  | ^^^");
+
+    // arguments and body tokens come from different lines, so their spans can't be joined
+    let input = format!(".macro m a\nLOAD 5 a 7\n.endm\n{}m 1", " ".repeat(30));
+    let err = &parse_code(&input).unwrap_err()[0];
+    assert_eq!(err.render(), format!("error: `LOAD` takes 1 operand(s) but 3 were given\n \
+                                      --> <input>:4:33\n  |\n4 | {0}m 1\n  | {0}  ^", " ".repeat(30)));
+    let input = format!(".macro m a\nLOAD a+1\n.endm\n{}m 0x7FFFFFFF", " ".repeat(20));
+    let err = &parse_code(&input).unwrap_err()[0];
+    assert_eq!(err.kind, ParseErrorKind::ExpressionOverflow);
+    assert_eq!(err.render(), format!("error: expression does not fit in 32 bits\n \
+                                      --> <input>:4:23\n  |\n4 | {0}m 0x7FFFFFFF\n  | {0}  ^^^^^^^^^^",
+                                     " ".repeat(20)));
+
+    let input = ".macro open\nNOOP";
+    assert_eq!(parse_code(input).unwrap_err()[0].kind,
+               ParseErrorKind::Unterminated { directive: ".macro".to_string(), terminator: ".endm" });
+}
+
+#[cfg(test)]
+fn parse_files(files: &[(&str, &str)]) -> Result<Program, Vec<ParseError>> {
+    let mut load = |path: &std::path::Path| {
+        return match files.iter().find(|(name, _)| std::path::Path::new(name) == path) {
+            Some((_, text)) => Ok(text.to_string()),
+            None => Err(std::io::Error::from(std::io::ErrorKind::NotFound)),
+        };
+    };
+    return parse_source_with(files[0].0, files[0].1, &ParseOptions::default(), &mut load);
+}
+
+#[test]
+fn parse_include_test() {
+    let program = parse_files(&[
+        ("prog/main.aaaasm", ".include \"lib/math.aaaasm\"\n.include \"lib/io.aaaasm\"\nstart: JUMP math::double\nmul: JUMP io::mul"),
+        ("prog/lib/math.aaaasm", "double: R_ADD r0\nmul: JUMP mul\nJUMP start"),
+        ("prog/lib/io.aaaasm", ".include \"../lib/./math.aaaasm\"\nmul: JUMP math::mul"),
+    ]);
+    let goal = Ok(vec![Instruction::R_ADD(0), Instruction::JUMP(1), Instruction::JUMP(4),
+                       Instruction::JUMP(1), Instruction::JUMP(0), Instruction::JUMP(3)]);
+    assert_eq!(program.map(|program| program.instructions), goal);
+}
+
+#[test]
+fn parse_include_fail() {
+    let errors = parse_files(&[
+        ("main.aaaasm", ".include \"a.aaaasm\"\n.include \"missing.aaaasm\"\nJUMP double"),
+        ("a.aaaasm", "NOOP\n.include \"b.aaaasm\"\ndouble: NOOP"),
+        ("b.aaaasm", ".include \"a.aaaasm\""),
+    ]).unwrap_err();
+    let found:Vec<_> = errors.iter().map(|err| (err.span.file.to_string(), err.span.line, err.kind.clone())).collect();
+    assert_eq!(found, vec![
+        ("main.aaaasm".to_string(), 2, ParseErrorKind::IncludeFailed {
+            path: "missing.aaaasm".to_string(),
+            reason: std::io::Error::from(std::io::ErrorKind::NotFound).to_string(),
+        }),
+        ("main.aaaasm".to_string(), 3, ParseErrorKind::UndefinedSymbol("double".to_string())),
+        ("b.aaaasm".to_string(), 1, ParseErrorKind::IncludeCycle(vec![
+            "a.aaaasm".to_string(), "b.aaaasm".to_string(), "a.aaaasm".to_string()])),
+    ]);
+}
+
+#[test]
+fn parse_conditional_include_test() {
+    // the file doesn't exist, but it's only read with DEBUG defined
+    let input = ".ifdef DEBUG\n.include \"debug.aaaasm\"\n.endif\nLOAD 1";
+    assert_eq!(parse_with_defines(input, &[]).map(|program| program.instructions), Ok(vec![Instruction::LOAD(1)]));
+
+    let options = ParseOptions { defines: vec![("DEBUG".to_string(), 1)], ..ParseOptions::default() };
+    let program = parse_source_with("<input>", input, &options, &mut |_| Ok("trace: NOOP".to_string()));
+    assert_eq!(program.map(|program| program.instructions), Ok(vec![Instruction::NOOP(), Instruction::LOAD(1)]));
+}
+
+#[cfg(test)]
+fn parse_with_defines(input: &str, defines: &[(&str, i32)]) -> Result<Program, Vec<ParseError>> {
+    let options = ParseOptions {
+        defines: defines.iter().map(|(name, value)| (name.to_string(), *value)).collect(),
+        ..ParseOptions::default()
+    };
+    return parse_source_with("<input>", input, &options, &mut |path| std::fs::read_to_string(path));
+}
+
+#[test]
+fn parse_conditionals_test() {
+    let input = ".ifndef SIZE\n.equ SIZE 4\n.endif\n\
+                 .macro count n\n.if n\nI_ADD 1\ncount n - 1\n.endif\n.endm\n\
+                 .ifdef DEBUG\n    .if SIZE - 4\n    LOAD 1\n    .else\n    LOAD 2\n    .endif\n.else\nLOAD 3\n.endif\n\
+                 count SIZE / 2";
+    let goal = Ok(vec![Instruction::LOAD(3), Instruction::I_ADD(1), Instruction::I_ADD(1)]);
+    assert_eq!(parse_with_defines(input, &[]).map(|program| program.instructions), goal);
+
+    let goal = Ok(vec![Instruction::LOAD(1), Instruction::I_ADD(1), Instruction::I_ADD(1),
+                       Instruction::I_ADD(1), Instruction::I_ADD(1)]);
+    assert_eq!(parse_with_defines(input, &[("SIZE", 8), ("DEBUG", 0)]).map(|program| program.instructions), goal);
+
+    assert_eq!(parse_define("SIZE=0x10"), Ok(("SIZE".to_string(), 16)));
+    assert_eq!(parse_define("DEBUG"), Ok(("DEBUG".to_string(), 1)));
+    assert!(parse_define("r1=2").is_err());
+}
+
+#[test]
+fn parse_conditionals_fail() {
+    let input = ".equ SIZE 2\n.endif\n.if 1\n.else\n.else\n.endif\n.if\n.endif\n.macro m\n.if 1\n.endm\nm\n.ifdef SIZE";
+    let errors = parse_with_defines(input, &[("SIZE", 1)]).unwrap_err();
+    let kinds:Vec<_> = errors.iter().map(|err| (err.span.line, err.kind.clone())).collect();
+    assert_eq!(kinds, vec![
+        (1, ParseErrorKind::AlreadyDefined("SIZE".to_string())),
+        (2, ParseErrorKind::Unmatched(".endif".to_string())),
+        (5, ParseErrorKind::UnexpectedToken(".else".to_string())),
+        (7, ParseErrorKind::ExpectedExpression),
+        (10, ParseErrorKind::Unterminated { directive: ".if".to_string(), terminator: ".endif" }),
+        (13, ParseErrorKind::Unterminated { directive: ".ifdef".to_string(), terminator: ".endif" }),
+    ]);
+}
+
+#[test]
+fn parse_source_map_test() {
+    let input = ".macro twice\n    I_ADD 1\n    I_ADD 1\n.endm\n\n:) start here\n  LOAD 3 :) three\ntwice";
+    let lines:Vec<_> = parse_code(input).unwrap().source_map.into_iter()
+        .map(|location| (location.line, location.text))
+        .collect();
+    assert_eq!(lines, vec![(7, "LOAD 3 :) three".to_string()), (8, "twice".to_string()), (8, "twice".to_string())]);
+}
+
+#[test]
+fn parse_aliases_test() {
+    let input = ".alias counter r2\n.alias total R3\nload 5\na2r_store counter\nR_Add total\nm2r_load 7, counter";
+    let goal = Ok(vec![Instruction::LOAD(5), Instruction::A2R_STORE(2), Instruction::R_ADD(3),
+                       Instruction::M2R_LOAD(7, 2)]);
+    assert_eq!(parse_code(input).map(|program| program.instructions), goal);
+}
+
+#[test]
+fn parse_aliases_fail() {
+    let input = ".alias counter r2\n.alias counter r1\n.alias big r4\nLOAD counter\nLOAD counter + 1\n.alias x 3";
+    let errors = parse_code(input).unwrap_err();
+    let kinds:Vec<_> = errors.iter().map(|err| (err.span.line, err.kind.clone())).collect();
+    assert_eq!(kinds, vec![
+        (2, ParseErrorKind::DuplicateSymbol { name: "counter".to_string(), first_line: 1 }),
+        (3, ParseErrorKind::RegisterOutOfRange { register: 4, count: REG_NUMBER }),
+        (4, ParseErrorKind::WrongOperandKind { mnemonic: "LOAD".to_string(), expected: "number", found: "register" }),
+        (5, ParseErrorKind::AliasInExpression("counter".to_string())),
+        (6, ParseErrorKind::Expected("a register")),
+    ]);
+}
+
+#[test]
+fn format_source_test() {
+    let input = ".equ SIZE   4\n.equ DOWN -1\n\n.macro   bump n\ni_add n:) bump it\n.endm\nloop:   I_ADD -1 :) count down\n\
+                 m2r_load   BUF+4, R1\n  :( note\nJUMP_NEG loop\nbump -(SIZE*2)\n.data\nBUF: .fill SIZE,0\n\n.text\nR2M_STORE r1 -5\n\n";
+    let goal = ".equ SIZE 4\n\
+                .equ DOWN -1\n\
+                \n\
+                .macro bump n\n\
+                \x20   I_ADD    n            :) bump it\n\
+                .endm\n\
+                loop:\n\
+                \x20   I_ADD    -1           :) count down\n\
+                \x20   M2R_LOAD BUF + 4, r1\n\
+                \x20   :( note\n\
+                \x20   JUMP_NEG loop\n\
+                \x20   bump     -(SIZE * 2)\n\
+                .data\n\
+                BUF:\n\
+                \x20   .fill    SIZE, 0\n\
+                \n\
+                .text\n\
+                \x20   R2M_STORE r1 -5\n";
+    let formatted = format_source(input);
+    assert_eq!(formatted, goal);
+    assert_eq!(format_source(&formatted), formatted);
+    assert_eq!(parse_code(&formatted).map(|program| program.instructions),
+               parse_code(input).map(|program| program.instructions));
+}
+
+// any valid instruction
+#[cfg(test)]
+fn instruction() -> impl Strategy<Value = Instruction> {
+    return (0..INSTRUCTIONS.len(), any::<[i32; 3]>()).prop_map(|(idx, values)| {
+        let info = &INSTRUCTIONS[idx];
+        let operands: Vec<i32> = info.operands.iter().zip(values)
+            .map(|(kind, value)| match kind {
+                OperandKind::Register => value.rem_euclid(REG_NUMBER as i32),
+                _ => value,
+            })
+            .collect();
+        return Instruction::from_parts(info.mnemonic, &operands).unwrap();
+    });
+}
+
+#[cfg(test)]
+fn load_lib(path: &std::path::Path) -> std::io::Result<String> {
+    return match path.to_str() {
+        Some("lib.aaaasm") => Ok("twice: R_ADD r0\nR_ADD r0\n.equ TWO 2".to_string()),
+        _ => Err(std::io::Error::from(std::io::ErrorKind::NotFound)),
+    };
+}
+
+// lines of a small program, with enough labels, macros and conditions that
+// an edit can change what other lines mean
+#[cfg(test)]
+fn source_line() -> impl Strategy<Value = String> {
+    let line = prop::sample::select(vec![
+        "start: LOAD 3", "loop: I_ADD -1", "JUMP_NEG done", "JUMP loop", "done: NOOP", ".loop: NOOP",
+        "1: JUMP 1b", "JUMP 1f", ".equ SIZE 4", "LOAD SIZE * 2", ".macro twice r", "R_ADD r", ".endm",
+        "twice r1", ".if SIZE", ".else", ".endif", ".data", ".text", ".word 1, 2", ".fill SIZE, 0",
+        ".string \"a :) b\"", ".include \"lib.aaaasm\"", "JUMP lib::twice", ".entry loop", ".alias acc r2",
+        "A2R_STORE acc", "  :) a comment", "", "R_ADD r9", "LOAD (1 +",
+    ]);
+    let ending = prop::sample::select(vec!["\n", "\r\n", "  \n", " :( done\n"]);
+    return (line, ending).prop_map(|(line, ending)| format!("{}{}", line, ending));
+}
+
+#[cfg(test)]
+fn text_edit() -> impl Strategy<Value = (prop::sample::Index, prop::sample::Index, String)> {
+    let replacement = prop_oneof![
+        source_line(),
+        "[ a-z0-9:.()\\r\\n-]{0,6}",
+        Just(String::new()),
+    ];
+    return (any::<prop::sample::Index>(), any::<prop::sample::Index>(), replacement);
+}
+
+#[cfg(test)]
+proptest! {
+    #[test]
+    fn display_round_trip_test(ins in instruction()) {
+        prop_assert_eq!(parse_instruction(&ins.to_string()), Ok(ins));
+    }
+
+    #[test]
+    fn disassemble_round_trip_test(program in prop::collection::vec(instruction(), 1..30)) {
+        // point most jumps somewhere in or just past the program, so there are labels
+        let len = program.len() as i32;
+        let program: Vec<_> = program.into_iter()
+            .map(|ins| match ins {
+                Instruction::JUMP(x) => Instruction::JUMP(x.rem_euclid(len + 1)),
+                Instruction::JUMP_NEG(x) if x % 4 != 0 => Instruction::JUMP_NEG(x.rem_euclid(len)),
+                ins => ins,
+            })
+            .collect();
+        prop_assert_eq!(parse_code(&disassemble(&program)).map(|program| program.instructions), Ok(program));
+    }
+
+    #[test]
+    fn document_edit_test(lines in prop::collection::vec(source_line(), 0..12),
+                          edits in prop::collection::vec(text_edit(), 1..6)) {
+        let mut text = lines.concat();
+        let mut doc = Document::parse("<input>", &text, ParseOptions::default(), &mut load_lib);
+        for (start, end, replacement) in edits {
+            let (start, end) = (start.index(text.len() + 1), end.index(text.len() + 1));
+            let range = start.min(end)..start.max(end);
+            text.replace_range(range.clone(), &replacement);
+            doc.edit(range, &replacement, &mut load_lib);
+
+            prop_assert_eq!(doc.text(), text.clone());
+            prop_assert_eq!(doc.tree(), &SyntaxTree::parse("<input>", &text));
+            let options = ParseOptions::default();
+            prop_assert_eq!(doc.result(), &parse_source_with("<input>", &text, &options, &mut load_lib));
+        }
+    }
+
+    #[test]
+    fn syntax_tree_round_trip_test(s in "[ a-z0-9:;()',\\t\\r\\n\"-]*") {
+        prop_assert_eq!(SyntaxTree::parse("<input>", &s).to_string(), s);
+    }
+}
+
+#[test]
+fn disassemble_test() {
+    let program = vec![Instruction::LOAD(-3), Instruction::I_ADD(1), Instruction::JUMP_NEG(1),
+                       Instruction::A2R_STORE(2), Instruction::JUMP(0), Instruction::JUMP(9)];
+    let goal = "L0:\n\
+                \x20   LOAD      -3\n\
+                L1:\n\
+                \x20   I_ADD     1\n\
+                \x20   JUMP_NEG  L1\n\
+                \x20   A2R_STORE r2\n\
+                \x20   JUMP      L0\n\
+                \x20   JUMP      9\n";
+    assert_eq!(disassemble(&program), goal);
+}
+
+#[test]
+fn parse_local_labels_test() {
+    let input = "first:\n.loop: I_ADD -1\nJUMP_NEG .loop\nsecond: LOAD 2\n.loop: JUMP .loop\n\
+                 1: JUMP 1f\n1: JUMP 1b\nJUMP 1b\n.macro spin\n1: JUMP 1b\n.endm\nspin\nspin\nJUMP first.loop";
+    let goal = vec![Instruction::I_ADD(-1), Instruction::JUMP_NEG(0), Instruction::LOAD(2), Instruction::JUMP(3),
+                    Instruction::JUMP(5), Instruction::JUMP(5), Instruction::JUMP(5),
+                    Instruction::JUMP(7), Instruction::JUMP(8)];
+    let program = parse_code(&input.replace("\nJUMP first.loop", ""));
+    assert_eq!(program.map(|program| program.instructions), Ok(goal));
+
+    // the renamed labels can't be written directly
+    assert!(parse_code(input).is_err());
+
+    // local labels in a macro are new in each expansion
+    let input = "start:\n.macro spin\n.l: I_ADD 1\nJUMP_NEG .l\nJUMP .end\n.endm\nspin\nspin\n.end: NOOP";
+    assert_eq!(parse_code(input).map(|program| program.instructions), Ok(vec![
+        Instruction::I_ADD(1), Instruction::JUMP_NEG(0), Instruction::JUMP(6),
+        Instruction::I_ADD(1), Instruction::JUMP_NEG(3), Instruction::JUMP(6), Instruction::NOOP(),
+    ]));
+}
+
+#[test]
+fn parse_local_labels_fail() {
+    let input = ".early: NOOP\nJUMP .early\nstart:\n.x: NOOP\n.x: NOOP\nJUMP 1b\nJUMP 2f\n2: NOOP";
+    let errors = parse_code(input).unwrap_err();
+    let kinds:Vec<_> = errors.iter().map(|err| (err.span.line, err.kind.clone())).collect();
+    assert_eq!(kinds, vec![
+        (1, ParseErrorKind::NoGlobalLabel(".early".to_string())),
+        (2, ParseErrorKind::NoGlobalLabel(".early".to_string())),
+        (5, ParseErrorKind::DuplicateSymbol { name: "start.x".to_string(), first_line: 4 }),
+        (6, ParseErrorKind::MissingNumericLabel("1b".to_string())),
+    ]);
+    assert_eq!(errors[3].kind.to_string(), "there is no `1:` label before `1b`");
+}
+
+#[test]
+fn parse_entry_test() {
+    let input = "double: R_ADD r0\nJUMP done\n.entry main\nmain: LOAD 4\nA2R_STORE r0\nJUMP double\ndone: NOOP";
+    assert_eq!(parse_code(input).map(|program| program.machine.initial_pc), Ok(2));
+    assert_eq!(parse_code("NOOP").map(|program| program.machine.initial_pc), Ok(0));
+    assert_eq!(parse_code("").map(|program| program.machine.initial_pc), Ok(0));
+
+    let options = ParseOptions { entry: Some("done - 1".to_string()), ..ParseOptions::default() };
+    let program = parse_source_with("<input>", input, &options, &mut |path| std::fs::read_to_string(path));
+    assert_eq!(program.map(|program| program.machine.initial_pc), Ok(4));
+}
+
+#[test]
+fn parse_entry_fail() {
+    let input = ".entry 3\n.entry start\nstart: NOOP";
+    let errors = parse_code(input).unwrap_err();
+    let kinds:Vec<_> = errors.iter().map(|err| (err.span.line, err.kind.clone())).collect();
+    assert_eq!(kinds, vec![
+        (1, ParseErrorKind::EntryOutOfRange(3)),
+        (2, ParseErrorKind::DuplicateDirective { directive: ".entry".to_string(), first_line: 1 }),
+    ]);
+
+    let options = ParseOptions { entry: Some("nowhere".to_string()), ..ParseOptions::default() };
+    let errors = parse_source_with("<input>", "NOOP", &options, &mut |path| std::fs::read_to_string(path)).unwrap_err();
+    assert_eq!(errors[0].to_string(), "<command line>:1:1: `nowhere` is not defined");
+}
+
+#[test]
+fn syntax_tree_test() {
+    let input = "loop:\tI_ADD -1 :( sad\r\n\n  :) happy \r\n.string \"a :) b\"";
+    let tree = SyntaxTree::parse("<input>", input);
+    assert_eq!(tree.to_string(), input);
+
+    let endings:Vec<_> = tree.lines.iter().map(|line| line.ending).collect();
+    assert_eq!(endings, vec!["\r\n", "\n", "\r\n", ""]);
+    let offsets:Vec<_> = tree.lines.iter().map(|line| line.offset).collect();
+    assert_eq!(offsets, vec![0, 23, 24, 37]);
+    assert!(tree.lines[1].is_blank());
+
+    let kinds:Vec<_> = tree.lines[0].tokens.iter().map(|token| token.kind).collect();
+    assert_eq!(kinds, vec![
+        TokenKind::Word, TokenKind::Colon, TokenKind::Whitespace, TokenKind::Word, TokenKind::Whitespace,
+        TokenKind::Minus, TokenKind::Word, TokenKind::Whitespace, TokenKind::Comment(CommentKind::Sad),
+    ]);
+    assert_eq!(tree.lines[2].comment().map(|token| token.text.as_str()), Some(":) happy "));
+    assert_eq!(tree.lines[3].significant().count(), 2);
+}
+
+#[test]
+fn syntax_tree_edit_test() {
+    let mut tree = SyntaxTree::parse("<input>", "a: NOOP\r\nJUMP a\nLOAD 1");
+    assert_eq!(tree.edit(3..7, "LOAD 2\nb:"), 0..2);
+    assert_eq!(tree.to_string(), "a: LOAD 2\nb:\r\nJUMP a\nLOAD 1");
+    assert_eq!(tree, SyntaxTree::parse("<input>", "a: LOAD 2\nb:\r\nJUMP a\nLOAD 1"));
+
+    assert_eq!(tree.edit(12..21, ""), 1..2);
+    assert_eq!(tree.to_string(), "a: LOAD 2\nb:LOAD 1");
+    assert_eq!(tree.lines[1].tokens[0].span.line, 2);
+}
+
+#[test]
+fn document_edit_label_test() {
+    let mut doc = Document::parse("<input>", "JUMP end\nend: NOOP", ParseOptions::default(),
+                                  &mut |path| std::fs::read_to_string(path));
+    assert!(doc.result().is_ok());
+    doc.edit(9..12, "fin", &mut |path| std::fs::read_to_string(path));
+    assert_eq!(doc.result().as_ref().unwrap_err()[0].kind, ParseErrorKind::UndefinedSymbol("end".to_string()));
+    doc.edit(5..8, "fin", &mut |path| std::fs::read_to_string(path));
+    assert_eq!(doc.result().as_ref().map(|program| program.instructions.clone()),
+               Ok(vec![Instruction::JUMP(1), Instruction::NOOP()]));
+}
+
+#[test]
+fn document_edit_include_test() {
+    let mut loads = 0;
+    let mut load = |_: &Path| {
+        loads += 1;
+        return Ok("two: LOAD 2".to_string());
+    };
+    let mut doc = Document::parse("<input>", ".include \"lib\"\nLOAD 1\nJUMP nowhere", ParseOptions::default(),
+                                  &mut load);
+    assert_eq!(doc.edit(21..21, "\nNOOP", &mut load), 1..3);
+    // the error comes from a line that wasn't parsed again, but it moved down
+    assert_eq!(doc.result().as_ref().unwrap_err()[0].span.line, 4);
+    doc.reload(&mut load);
+    assert_eq!(loads, 2);
+}
+
+#[test]
+fn parse_machine_test() {
+    let input = ".machine 8, SIZE * 2\n.equ SIZE 1024\nR2A_LOAD r7\n.data\n.org 2047\n.word 1";
+    let program = parse_code(input).unwrap();
+    assert_eq!(program.machine, MachineConfig { registers: 8, memory: 2048, ..MachineConfig::default() });
+    assert_eq!(program.instructions, vec![Instruction::R2A_LOAD(7)]);
+
+    let options = ParseOptions { registers: Some(16), ..ParseOptions::default() };
+    let program = parse_source_with("<input>", input, &options, &mut |path| std::fs::read_to_string(path));
+    assert_eq!(program.map(|program| program.machine.registers), Ok(16));
+
+    let input = ".machine 4, 1024, saturating";
+    assert_eq!(parse_code(input).map(|program| program.machine.overflow), Ok(OverflowMode::Saturating));
+    let options = ParseOptions { overflow: Some(OverflowMode::Wrapping), ..ParseOptions::default() };
+    let program = parse_source_with("<input>", input, &options, &mut |path| std::fs::read_to_string(path));
+    assert_eq!(program.map(|program| program.machine.overflow), Ok(OverflowMode::Wrapping));
+
+    let errors = parse_code("R2A_LOAD r7").unwrap_err();
+    assert_eq!(errors[0].kind, ParseErrorKind::RegisterOutOfRange { register: 7, count: REG_NUMBER });
+    assert_eq!(errors[0].span.start, 9);
+}
+
+#[test]
+fn parse_machine_fail() {
+    let input = ".machine 2, -1\n.alias acc r2\n.machine 4, 1024\nA2R_STORE r3\n.data\n.word 5";
+    let errors = parse_code(input).unwrap_err();
+    let kinds:Vec<_> = errors.iter().map(|err| (err.span.line, err.kind.to_string())).collect();
+    assert_eq!(kinds, vec![
+        (1, "a machine can have from 0 to 16777216 words of memory, not -1".to_string()),
+        (2, "there is no register r2, registers go from r0 to r1".to_string()),
+        (3, "`.machine` was already used on line 1".to_string()),
+        (4, "there is no register r3, registers go from r0 to r1".to_string()),
+    ]);
+
+    let options = ParseOptions { memory: Some(0), ..ParseOptions::default() };
+    let errors = parse_source_with("<input>", ".data\n.word 5", &options,
+                                   &mut |path| std::fs::read_to_string(path)).unwrap_err();
+    assert_eq!(errors[0].kind, ParseErrorKind::DataOutOfRange { address: 0, len: 1 });
+
+    let options = ParseOptions { registers: Some(1 << 20), ..ParseOptions::default() };
+    let errors = parse_source_with("<input>", "NOOP", &options, &mut |path| std::fs::read_to_string(path)).unwrap_err();
+    assert_eq!(errors[0].to_string(), "<command line>:1:1: a machine can have from 0 to 65536 registers, not 1048576");
+
+    let errors = parse_code(".machine 4, 1024, clamping").unwrap_err();
+    assert_eq!(errors[0].kind, ParseErrorKind::Expected("wrapping, saturating or trapping"));
+    assert_eq!(errors[0].span.start, 18);
+}
+
+#[test]
+fn parse_arithmetic_test() {
+    let program = parse_code("i_sub 1\nR_MUL r2\nI_DIV 3\nR_MOD r0\nNEG");
+    assert_eq!(program.map(|program| program.instructions), Ok(vec![
+        Instruction::I_SUB(1), Instruction::R_MUL(2), Instruction::I_DIV(3), Instruction::R_MOD(0), Instruction::NEG(),
+    ]));
+}
+
+#[test]
+fn parse_huge_fill_fail() {
+    // neither of these should try to build the block
+    let errors = parse_code(".data\n.fill 2000000000, 0").unwrap_err();
+    assert_eq!(errors.iter().map(|err| err.kind.clone()).collect::<Vec<_>>(),
+               vec![ParseErrorKind::FillTooLarge { count: 2000000000, max: MAX_MEMORY }]);
+
+    let errors = parse_code(".data\n.fill 10000000, 0").unwrap_err();
+    assert_eq!(errors.iter().map(|err| err.kind.clone()).collect::<Vec<_>>(),
+               vec![ParseErrorKind::DataOutOfRange { address: 0, len: 10000000 }]);
+}